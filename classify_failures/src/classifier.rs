@@ -0,0 +1,59 @@
+//! Rule-based classification of build logs
+
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Reverse;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Category of failures that don't match any rule
+pub const UNKNOWN: &str = "unknown";
+
+struct Rule {
+    priority: i64,
+    category: String,
+    regex: Regex,
+}
+
+/// Set of rules, sorted by descending priority
+pub struct Classifier {
+    rules: Vec<Rule>,
+}
+
+impl Classifier {
+    /// Reads the rules from a file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut rules = vec![];
+        for (num, line) in read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.splitn(3, ' ').collect();
+            if parts.len() != 3 {
+                return Err(anyhow!(
+                    "Invalid rule in line {} of {}: {line}",
+                    num + 1,
+                    path.display()
+                ));
+            }
+            rules.push(Rule {
+                priority: parts[0].parse::<i64>()?,
+                category: parts[1].to_string(),
+                regex: RegexBuilder::new(parts[2]).multi_line(true).build()?,
+            });
+        }
+        // Stable sort, so earlier rules win on equal priority
+        rules.sort_by_key(|rule| Reverse(rule.priority));
+        Ok(Self { rules })
+    }
+
+    /// Returns the category of a failure by its Hydra status and log tail
+    pub fn classify(&self, status: &str, log: &str) -> &str {
+        let text = format!("hydra-status: {status}\n{log}");
+        self.rules
+            .iter()
+            .find(|rule| rule.regex.is_match(&text))
+            .map_or(UNKNOWN, |rule| rule.category.as_str())
+    }
+}
@@ -0,0 +1,84 @@
+//! Classify the direct failures of some evaluations by their build logs
+
+mod classifier;
+
+use anyhow::{anyhow, Result};
+use classifier::Classifier;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, File};
+use std::io::Write as _;
+use std::path::PathBuf;
+use zhf_common::cache::{data_dir, log_file, read_eval_cache};
+
+/// Rule file that is used if none is given
+const DEFAULT_RULES: &str = "rules/failure-classes.conf";
+
+fn main() -> Result<()> {
+    env_logger::builder().format_timestamp(None).init();
+    // Handle args
+    let mut evals: Vec<u64> = Vec::new();
+    let mut rules = PathBuf::from(DEFAULT_RULES);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rules" => {
+                rules = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--rules needs a value"))?
+                    .into();
+            }
+            _ => evals.push(arg.parse::<u64>()?),
+        }
+    }
+    log::info!("Will classify evaluations: {:?}", evals);
+    let classifier = Classifier::from_file(&rules)?;
+
+    // Prepare directories
+    let data_dir = data_dir()?;
+    let mut class_dir = data_dir.clone();
+    class_dir.push("classcache");
+    create_dir_all(&class_dir)?;
+
+    // Classify. This is always done from scratch, so changes to the rules apply immediately.
+    for eval in &evals {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut out_loc = class_dir.clone();
+        out_loc.push(format!("{eval}.cache.new"));
+        let mut out = File::create(&out_loc)?;
+        for build in read_eval_cache(&data_dir, *eval)? {
+            if !build.is_direct_failure() {
+                continue;
+            }
+            let log = read_to_string(log_file(&data_dir, build.build_id)).unwrap_or_default();
+            let category = classifier.classify(&build.status, &log);
+            *counts.entry(category).or_default() += 1;
+            out.write_fmt(format_args!("{} {category}\n", build.build_id))?;
+        }
+        let mut final_loc = class_dir.clone();
+        final_loc.push(format!("{eval}.cache"));
+        std::fs::rename(out_loc, final_loc)?;
+        log::info!("Categories of evaluation {eval}: {counts:?}");
+    }
+
+    // Clean cache
+    log::info!("Cleaning cache");
+    for path in std::fs::read_dir(class_dir)? {
+        let path = path?;
+        let file_name = path.file_name();
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| anyhow!("Cache entry has no filename"))?;
+        let id = if let Some(Ok(id)) = file_name.strip_suffix(".cache").map(str::parse::<u64>) {
+            id
+        } else {
+            // Invalid entry
+            continue;
+        };
+        if !evals.contains(&id) {
+            log::info!("Purging classification of eval {id}");
+            std::fs::remove_file(path.path())?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,13 +1,18 @@
 //! Crawl the full table of all builds from a evaluation
+//!
+//! The nixpkgs revision each evaluation was built from is appended to `evalrevisions` as well, so
+//! later steps don't have to ask Hydra for it. Afterwards, the failed builds of each system are
+//! counted into `failcache` for the history of the failure counts.
 
-use anyhow::Result;
-use reqwest_middleware::ClientBuilder;
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use anyhow::{anyhow, Result};
 use select::node::Node;
 use select::predicate::Name;
-use std::collections::HashMap;
-use std::fs::{create_dir_all, File};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, rename, File, OpenOptions};
 use std::io::Write as _;
+use std::path::Path;
+use zhf_common::cache::{read_eval_revisions, read_failed_builds};
+use zhf_common::http::hydra_client;
 
 #[tokio::main(worker_threads = 4)]
 async fn main() -> Result<()> {
@@ -39,15 +44,34 @@ async fn main() -> Result<()> {
     eval_cache_dir.push("evalcache");
     create_dir_all(&eval_cache_dir)?;
 
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(10);
-    let http_client = ClientBuilder::new(
-        reqwest::Client::builder()
-            .user_agent("zh.fail scraper, please contact @dasJ on GitHub")
-            .build()?,
-    )
-    .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-    .build();
+    let (http_client, rate_limiter) = hydra_client()?;
 
+    // Record the revisions of the evaluations
+    let revisions = read_eval_revisions(&data_dir)?;
+    let mut revisions_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join("evalrevisions"))?;
+    for (eval_id, _) in &argv {
+        if revisions.contains_key(eval_id) {
+            continue;
+        }
+        let eval: serde_json::Value = http_client
+            .get(format!("https://hydra.nixos.org/eval/{eval_id}"))
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let revision = eval["jobsetevalinputs"]["nixpkgs"]["revision"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Evaluation {eval_id} has no nixpkgs revision"))?;
+        log::info!("Evaluation {eval_id} is nixpkgs revision {revision}");
+        writeln!(revisions_file, "{eval_id} {revision}")?;
+    }
+
+    let evals: Vec<u64> = argv.iter().map(|(eval_id, _)| *eval_id).collect();
     for (eval_id, eval_nixos) in argv {
         let mut cache_file = eval_cache_dir.clone();
         cache_file.push(format!("{eval_id}.cache"));
@@ -147,5 +171,38 @@ async fn main() -> Result<()> {
             out.write_fmt(format_args!("{attr} {build}\n"))?;
         }
     }
+    rate_limiter.log_stats();
+    rate_limiter.write_stats(&data_dir, "crawl_evals")?;
+
+    write_fail_cache(&data_dir, &evals)
+}
+
+/// Counts the failed builds of each system like the landing page does and writes them to
+/// `failcache`, unless these evaluations were already counted
+fn write_fail_cache(data_dir: &Path, evals: &[u64]) -> Result<()> {
+    let fail_cache_dir = data_dir.join("failcache");
+    create_dir_all(&fail_cache_dir)?;
+    let name = evals
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let cache_file = fail_cache_dir.join(format!("{name}.cache"));
+    if cache_file.exists() {
+        log::info!("Failed builds of {name} are already counted");
+        return Ok(());
+    }
+
+    let mut systems: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, build) in read_failed_builds(data_dir, evals)? {
+        *systems.entry(build.arch).or_default() += 1;
+    }
+    // Write to a temporary file first, so aborted runs don't leave half of it
+    let new_file = fail_cache_dir.join(format!("{name}.cache.new"));
+    let mut out = File::create(&new_file)?;
+    for (system, count) in systems {
+        writeln!(out, "{system} {count}")?;
+    }
+    rename(new_file, cache_file)?;
     Ok(())
 }
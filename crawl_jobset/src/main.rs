@@ -2,9 +2,9 @@
 //! We need to do this because the API doesn't offer this data.
 
 use anyhow::{anyhow, Result};
-use reqwest_middleware::ClientBuilder;
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use select::predicate::{Class, Name};
+use zhf_common::cache::data_dir;
+use zhf_common::http::hydra_client;
 
 #[tokio::main(worker_threads = 4)]
 async fn main() -> Result<()> {
@@ -13,14 +13,7 @@ async fn main() -> Result<()> {
     let project = &argv[1];
     let jobset = &argv[2];
 
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(10);
-    let http_client = ClientBuilder::new(
-        reqwest::Client::builder()
-            .user_agent("zh.fail scraper, please contact @dasJ on GitHub")
-            .build()?,
-    )
-    .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-    .build();
+    let (http_client, rate_limiter) = hydra_client()?;
 
     let res = http_client
         .get(format!(
@@ -31,6 +24,8 @@ async fn main() -> Result<()> {
         .error_for_status()?
         .text()
         .await?;
+    rate_limiter.log_stats();
+    rate_limiter.write_stats(&data_dir()?, &format!("crawl_jobset-{project}"))?;
     // Parse output
     let doc = select::document::Document::from(&res[..]);
     let eval_table = doc
@@ -0,0 +1,212 @@
+//! Fetch the tails of the build logs of all direct failures of some evaluations, and of all
+//! failed source fetches that block other builds
+
+use anyhow::{anyhow, Result};
+use reqwest_middleware::ClientWithMiddleware;
+use std::collections::BTreeSet;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use zhf_common::cache::{
+    cache_file, data_dir, log_file, read_eval_cache, read_most_important_cache, step_log_file,
+};
+use zhf_common::http::hydra_client;
+use zhf_common::store_path::looks_like_source;
+
+/// Number of parallel HTTP requests that are sent to Hydra
+const PARALLEL_REQUESTS: usize = 4;
+/// Number of log lines that are kept by default
+const DEFAULT_LOG_LINES: usize = 30;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::builder().format_timestamp(None).init();
+    // Handle args
+    let mut evals: Vec<u64> = Vec::new();
+    let mut log_lines = DEFAULT_LOG_LINES;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lines" => {
+                log_lines = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--lines needs a value"))?
+                    .parse::<usize>()?;
+            }
+            _ => evals.push(arg.parse::<u64>()?),
+        }
+    }
+    log::info!("Will crawl logs of evaluations: {:?}", evals);
+
+    // Prepare directories
+    let data_dir = data_dir()?;
+    let mut log_dir = data_dir.clone();
+    log_dir.push("logcache");
+    create_dir_all(&log_dir)?;
+
+    // Find all direct failures
+    let mut build_ids = BTreeSet::new();
+    for eval in &evals {
+        for build in read_eval_cache(&data_dir, *eval)? {
+            if build.is_direct_failure() {
+                build_ids.insert(build.build_id);
+            }
+        }
+    }
+    // Find all failed source fetches. They usually fail as a step of another build, so the log
+    // of the step is needed rather than the one of the build.
+    let mut source_steps = BTreeSet::new();
+    for eval in &evals {
+        if !cache_file(&data_dir, "mostimportantcache", *eval).exists() {
+            continue;
+        }
+        for root in read_most_important_cache(&data_dir, *eval)? {
+            if let Some(step_nr) = root.step_nr {
+                if looks_like_source(&root.name) {
+                    source_steps.insert((root.build_id, step_nr));
+                }
+            }
+        }
+    }
+
+    let mut missing: Vec<(String, PathBuf)> = vec![];
+    for build_id in &build_ids {
+        let path = log_file(&data_dir, *build_id);
+        if !path.exists() {
+            missing.push((
+                format!("https://hydra.nixos.org/build/{build_id}/log"),
+                path,
+            ));
+        }
+    }
+    for (build_id, step_nr) in &source_steps {
+        let path = step_log_file(&data_dir, *build_id, *step_nr);
+        if !path.exists() {
+            missing.push((
+                format!("https://hydra.nixos.org/build/{build_id}/nixlog/{step_nr}"),
+                path,
+            ));
+        }
+    }
+    log::info!(
+        "Fetching {} logs of {} direct failures and {} failed source fetches",
+        missing.len(),
+        build_ids.len(),
+        source_steps.len()
+    );
+
+    if !missing.is_empty() {
+        let (http_client, rate_limiter) = hydra_client()?;
+        let http_semaphore = Arc::new(Semaphore::new(PARALLEL_REQUESTS));
+        let mut tasks = JoinSet::new();
+        for (url, path) in missing {
+            let http_client = http_client.clone();
+            let http_semaphore = http_semaphore.clone();
+            tasks.spawn(async move {
+                let permit = http_semaphore.acquire_owned().await?;
+                let res = fetch_log(&url, &http_client, log_lines, path).await;
+                drop(permit);
+                res.map_err(|e| anyhow!("Failed fetching log {url}: {e}"))
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            if let Err(e) = res? {
+                log::error!("{e}");
+            }
+        }
+        rate_limiter.log_stats();
+        rate_limiter.write_stats(&data_dir, "crawl_logs")?;
+    }
+
+    // Clean cache
+    log::info!("Cleaning cache");
+    for path in std::fs::read_dir(log_dir)? {
+        let path = path?;
+        let file_name = path.file_name();
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| anyhow!("Cache entry has no filename"))?;
+        let stem = if let Some(stem) = file_name.strip_suffix(".log") {
+            stem
+        } else {
+            // Invalid entry or leftover of an aborted run
+            std::fs::remove_file(path.path())?;
+            continue;
+        };
+        let keep = match stem.split_once('-') {
+            Some((build_id, step_nr)) => match (build_id.parse::<u64>(), step_nr.parse::<u64>()) {
+                (Ok(build_id), Ok(step_nr)) => source_steps.contains(&(build_id, step_nr)),
+                _ => false,
+            },
+            None => stem
+                .parse::<u64>()
+                .is_ok_and(|build_id| build_ids.contains(&build_id)),
+        };
+        if !keep {
+            log::debug!("Purging log {file_name}");
+            std::fs::remove_file(path.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetches the last lines of a log and writes them to `path`. `url` is the location of the log
+/// without the mode, like `https://hydra.nixos.org/build/<id>/log`.
+async fn fetch_log(
+    url: &str,
+    http_client: &ClientWithMiddleware,
+    log_lines: usize,
+    path: PathBuf,
+) -> Result<()> {
+    let res = http_client.get(format!("{url}/tail")).send().await?;
+    let log = if res.status().is_success() {
+        res.text().await?
+    } else {
+        // Hydra can't tail all logs, so fall back to the full log
+        log::debug!("No log tail for {url}, fetching the raw log");
+        http_client
+            .get(format!("{url}/raw"))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+    };
+
+    let log = strip_escapes(&log);
+    let lines: Vec<&str> = log.lines().collect();
+    let excerpt = lines[lines.len().saturating_sub(log_lines)..].join("\n");
+
+    // Write to a temporary file first so aborted runs don't leave half-written logs
+    let mut new_path = path.clone();
+    new_path.set_extension("log.new");
+    tokio::fs::write(&new_path, excerpt).await?;
+    tokio::fs::rename(new_path, path).await?;
+    Ok(())
+}
+
+/// Removes ANSI escape sequences and other control characters from a log
+fn strip_escapes(log: &str) -> String {
+    let mut out = String::with_capacity(log.len());
+    let mut chars = log.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip CSI sequences like colors until their final byte
+            if chars.peek() == Some(&'[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        if c == '\n' || c == '\t' || !c.is_control() {
+            out.push(c);
+        }
+    }
+    out
+}
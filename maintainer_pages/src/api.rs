@@ -0,0 +1,262 @@
+//! Machine-readable versions of the failure pages in `public/api/v1/`.
+//!
+//! Every document is available as JSON and as CSV:
+//!
+//! - `maintainers/{slug}.json`: failed builds of a maintainer (`_` for builds without maintainer).
+//!   The slug is the GitHub handle, unless it collides with another one (see [`crate::handles`]).
+//! - `all.json`: all failed builds
+//! - `summary.json`: number of failed builds per system, counted from the evaluation caches like on
+//!   the landing page, so builds without maintainer information are included
+//!
+//! The JSON documents are objects with these fields:
+//!
+//! - `schema_version`: [`SCHEMA_VERSION`], incremented on incompatible changes only. Fields may
+//!   be added without a new version.
+//! - `generated_at`: RFC 3339 timestamp of the run that generated the document
+//! - `evals`: list of `{ "id", "time" }` objects of the evaluations the data comes from. `time` is
+//!   the time Hydra reports for the evaluation, or `null` if it's unknown.
+//! - `maintainer`: only in the per-maintainer documents
+//! - `builds`: list of failed builds with the fields `attr`, `build_id`, `name`, `system`,
+//!   `status` (as reported by Hydra), `direct` (false if only a dependency failed), `category`
+//!   (failure category of direct failures or `null`), `url` and `maintainers` (GitHub handles).
+//!   In `summary.json`, this is replaced by `systems`, a list of `{ "system", "direct",
+//!   "indirect", "total" }` objects, and `total`, the number of all failed builds.
+//!
+//! The CSV files have a header and contain the `builds` (or `systems`) of the JSON document, with
+//! the maintainers separated by spaces. The schema version is not part of the CSV files, it's
+//! always the same as the one of the JSON documents.
+
+use crate::handles::Handles;
+use crate::{Build, Groups};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir_all, read_to_string, File};
+use std::path::Path;
+use zhf_common::cache::EvalBuild;
+
+/// Version of the schema of all documents
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An evaluation the data comes from
+#[derive(Serialize)]
+pub struct Eval {
+    pub id: u64,
+    pub time: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BuildsDocument<'a> {
+    schema_version: u32,
+    generated_at: &'a str,
+    evals: &'a [Eval],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maintainer: Option<&'a str>,
+    builds: &'a [ApiBuild],
+}
+
+#[derive(Serialize)]
+struct SummaryDocument<'a> {
+    schema_version: u32,
+    generated_at: &'a str,
+    evals: &'a [Eval],
+    systems: &'a [SystemSummary],
+    total: usize,
+}
+
+#[derive(Serialize)]
+struct ApiBuild {
+    attr: String,
+    build_id: u64,
+    name: String,
+    system: String,
+    status: String,
+    direct: bool,
+    category: Option<String>,
+    url: String,
+    maintainers: Vec<String>,
+}
+
+/// A build in the CSV files, which can't contain lists
+#[derive(Serialize)]
+struct CsvBuild<'a> {
+    attr: &'a str,
+    build_id: u64,
+    name: &'a str,
+    system: &'a str,
+    status: &'a str,
+    direct: bool,
+    category: Option<&'a str>,
+    url: &'a str,
+    maintainers: String,
+}
+
+#[derive(Serialize)]
+struct SystemSummary {
+    system: String,
+    direct: usize,
+    indirect: usize,
+    total: usize,
+}
+
+/// Reads the times of the evaluations from the history files
+pub fn read_evals(data_dir: &Path, evals: &[u64]) -> Vec<Eval> {
+    let mut times = HashMap::new();
+    for history in ["history-linux", "history-darwin"] {
+        let Ok(lines) = read_to_string(data_dir.join(history)) else {
+            continue;
+        };
+        for line in lines.lines() {
+            let mut parts = line.splitn(3, ' ');
+            if let (Some(Ok(eval)), Some(_), Some(time)) = (
+                parts.next().map(str::parse::<u64>),
+                parts.next(),
+                parts.next(),
+            ) {
+                times.insert(eval, time.to_string());
+            }
+        }
+    }
+    evals
+        .iter()
+        .map(|id| Eval {
+            id: *id,
+            time: times.get(id).cloned(),
+        })
+        .collect()
+}
+
+/// Writes the documents of the failed builds
+pub fn write(
+    out_dir: &Path,
+    generated_at: &str,
+    evals: &[Eval],
+    maintainers: &Groups,
+    handles: &Handles,
+    all_builds: &[&Build],
+    categories: &HashMap<u64, String>,
+) -> Result<()> {
+    let maintainers_dir = out_dir.join("maintainers");
+    create_dir_all(&maintainers_dir)?;
+
+    // All maintainers of each build
+    let mut build_maintainers: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (maintainer, builds) in maintainers {
+        for build in builds {
+            let entry = build_maintainers.entry(&build.attr).or_default();
+            if maintainer != "_" {
+                entry.insert(maintainer);
+            }
+        }
+    }
+    let to_api = |builds: &mut dyn Iterator<Item = &Build>| -> Vec<ApiBuild> {
+        builds
+            .map(|build| ApiBuild {
+                attr: build.attr.clone(),
+                build_id: build.build_id,
+                name: build.name.clone(),
+                system: build.arch.clone(),
+                status: build.status.clone(),
+                direct: build.status != "Dependency failed",
+                category: categories.get(&build.build_id).cloned(),
+                url: format!("https://hydra.nixos.org/build/{}", build.build_id),
+                maintainers: build_maintainers
+                    .get(build.attr.as_str())
+                    .map(|m| m.iter().map(|m| m.to_string()).collect())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    };
+
+    for (maintainer, builds) in maintainers {
+        let Some(handle) = handles.get(maintainer) else {
+            continue;
+        };
+        let slug = handle.slug();
+        let builds = to_api(&mut builds.iter());
+        let document = BuildsDocument {
+            schema_version: SCHEMA_VERSION,
+            generated_at,
+            evals,
+            maintainer: Some(maintainer),
+            builds: &builds,
+        };
+        write_json(&maintainers_dir.join(format!("{slug}.json")), &document)?;
+        write_builds_csv(&maintainers_dir.join(format!("{slug}.csv")), &builds)?;
+    }
+
+    let builds = to_api(&mut all_builds.iter().copied());
+    let document = BuildsDocument {
+        schema_version: SCHEMA_VERSION,
+        generated_at,
+        evals,
+        maintainer: None,
+        builds: &builds,
+    };
+    write_json(&out_dir.join("all.json"), &document)?;
+    write_builds_csv(&out_dir.join("all.csv"), &builds)?;
+    Ok(())
+}
+
+/// Writes the number of failed builds per system
+pub fn write_summary(
+    out_dir: &Path,
+    generated_at: &str,
+    evals: &[Eval],
+    failed_builds: &[(u64, EvalBuild)],
+) -> Result<()> {
+    let mut systems: BTreeMap<&str, SystemSummary> = BTreeMap::new();
+    for (_, build) in failed_builds {
+        let summary = systems.entry(&build.arch).or_insert_with(|| SystemSummary {
+            system: build.arch.clone(),
+            direct: 0,
+            indirect: 0,
+            total: 0,
+        });
+        if build.is_direct_failure() {
+            summary.direct += 1;
+        } else {
+            summary.indirect += 1;
+        }
+        summary.total += 1;
+    }
+    let systems: Vec<_> = systems.into_values().collect();
+    let document = SummaryDocument {
+        schema_version: SCHEMA_VERSION,
+        generated_at,
+        evals,
+        systems: &systems,
+        total: failed_builds.len(),
+    };
+    write_json(&out_dir.join("summary.json"), &document)?;
+    let mut csv = csv::Writer::from_path(out_dir.join("summary.csv"))?;
+    for system in &systems {
+        csv.serialize(system)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+fn write_json<T: Serialize>(path: &Path, document: &T) -> Result<()> {
+    serde_json::to_writer(File::create(path)?, document)?;
+    Ok(())
+}
+
+fn write_builds_csv(path: &Path, builds: &[ApiBuild]) -> Result<()> {
+    let mut csv = csv::Writer::from_path(path)?;
+    for build in builds {
+        csv.serialize(CsvBuild {
+            attr: &build.attr,
+            build_id: build.build_id,
+            name: &build.name,
+            system: &build.system,
+            status: &build.status,
+            direct: build.direct,
+            category: build.category.as_deref(),
+            url: &build.url,
+            maintainers: build.maintainers.join(" "),
+        })?;
+    }
+    csv.flush()?;
+    Ok(())
+}
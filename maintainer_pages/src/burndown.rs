@@ -0,0 +1,213 @@
+//! History of the failure counts for charts, in `public/data/burndown.json`.
+//!
+//! `history-linux` and `history-darwin` only contain the number of failed builds of each jobset,
+//! so the counts of each system are additionally recorded in `history-systems`. Its lines consist
+//! of the evaluation, the system, the number of direct and indirect failures and the time of the
+//! evaluation as shown by Hydra. Systems ending in `-darwin` are counted for the Darwin jobset, all
+//! others for the Linux jobset, like in the other history files.
+//!
+//! The document is an object with these fields:
+//!
+//! - `schema_version`: [`SCHEMA_VERSION`], incremented on incompatible changes only
+//! - `generated_at`: RFC 3339 timestamp of the run that generated the document
+//! - `systems`: object with a series for every system that was ever recorded
+//! - `totals`: object with the series `linux` and `darwin` of the whole jobsets
+//!
+//! A series is a list of `{ "eval", "time", "total", "direct", "indirect" }` objects ordered by
+//! evaluation, where `time` is the time of the evaluation in UTC, like `2023-05-02T10:06:44`.
+//! The totals go back further than the systems, `direct` and `indirect` are `null` for
+//! evaluations from before the systems were recorded.
+
+use crate::api::Eval;
+use crate::platforms::Platforms;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{create_dir_all, read_to_string, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use zhf_common::cache::read_history;
+
+/// Version of the schema of the document
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Failure counts of a system in an evaluation, as recorded in `history-systems`
+struct Record {
+    eval: u64,
+    system: String,
+    direct: usize,
+    indirect: usize,
+    time: String,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    generated_at: &'a str,
+    systems: BTreeMap<String, Vec<Point>>,
+    totals: BTreeMap<&'static str, Vec<Point>>,
+}
+
+/// A point of a series
+#[derive(Serialize)]
+struct Point {
+    eval: u64,
+    time: String,
+    total: usize,
+    direct: Option<usize>,
+    indirect: Option<usize>,
+}
+
+/// Records the failure counts of the systems, unless they were already recorded for their
+/// evaluation
+pub fn update(
+    data_dir: &Path,
+    linux_eval: &Eval,
+    darwin_eval: &Eval,
+    platforms: &Platforms,
+) -> Result<()> {
+    let recorded: HashSet<(u64, String)> = read_records(data_dir)?
+        .into_iter()
+        .map(|record| (record.eval, record.system))
+        .collect();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join("history-systems"))?;
+    for (system, builds) in platforms {
+        let eval = if system.ends_with("-darwin") {
+            darwin_eval
+        } else {
+            linux_eval
+        };
+        if recorded.contains(&(eval.id, system.clone())) {
+            continue;
+        }
+        let Some(time) = &eval.time else {
+            log::warn!(
+                "Not recording {system} without time of evaluation {}",
+                eval.id
+            );
+            continue;
+        };
+        let indirect = builds
+            .iter()
+            .filter(|build| build.status == "Dependency failed")
+            .count();
+        let direct = builds.len() - indirect;
+        writeln!(file, "{} {system} {direct} {indirect} {time}", eval.id)?;
+    }
+    Ok(())
+}
+
+/// Writes the series of all systems and the totals of the jobsets
+pub fn write(data_dir: &Path, out_dir: &Path, generated_at: &str) -> Result<()> {
+    let mut systems: BTreeMap<String, Vec<Point>> = BTreeMap::new();
+    // Splits of the jobsets by evaluation
+    let mut splits: HashMap<u64, (usize, usize)> = HashMap::new();
+    for record in read_records(data_dir)? {
+        let Some(time) = chart_time(&record.time) else {
+            log::warn!(
+                "Skipping evaluation {} with invalid time in history-systems",
+                record.eval
+            );
+            continue;
+        };
+        let split = splits.entry(record.eval).or_default();
+        split.0 += record.direct;
+        split.1 += record.indirect;
+        systems.entry(record.system).or_default().push(Point {
+            eval: record.eval,
+            time,
+            total: record.direct + record.indirect,
+            direct: Some(record.direct),
+            indirect: Some(record.indirect),
+        });
+    }
+    for series in systems.values_mut() {
+        series.sort_by_key(|point| point.eval);
+    }
+
+    let mut totals = BTreeMap::new();
+    for jobset in ["linux", "darwin"] {
+        let mut series = read_totals(data_dir, jobset)?;
+        for point in &mut series {
+            if let Some((direct, indirect)) = splits.get(&point.eval) {
+                point.direct = Some(*direct);
+                point.indirect = Some(*indirect);
+            }
+        }
+        totals.insert(jobset, series);
+    }
+
+    create_dir_all(out_dir)?;
+    let document = Document {
+        schema_version: SCHEMA_VERSION,
+        generated_at,
+        systems,
+        totals,
+    };
+    serde_json::to_writer(File::create(out_dir.join("burndown.json"))?, &document)?;
+    Ok(())
+}
+
+/// Reads `history-systems`, which doesn't exist before the first run
+fn read_records(data_dir: &Path) -> Result<Vec<Record>> {
+    let Ok(lines) = read_to_string(data_dir.join("history-systems")) else {
+        return Ok(vec![]);
+    };
+    let mut records = vec![];
+    for line in lines.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(5, ' ').collect();
+        if parts.len() != 5 {
+            return Err(anyhow!("Invalid line in history-systems: {line}"));
+        }
+        records.push(Record {
+            eval: parts[0].parse::<u64>()?,
+            system: parts[1].to_string(),
+            direct: parts[2].parse::<usize>()?,
+            indirect: parts[3].parse::<usize>()?,
+            time: parts[4].to_string(),
+        });
+    }
+    Ok(records)
+}
+
+/// Reads the failure counts of a jobset from its history, ordered by evaluation
+fn read_totals(data_dir: &Path, jobset: &str) -> Result<Vec<Point>> {
+    let history = read_history(data_dir, jobset)?;
+    if history.is_empty() {
+        log::warn!("No history of the {jobset} jobset");
+    }
+    let mut points = vec![];
+    for entry in history {
+        let Some(time) = chart_time(&entry.time) else {
+            log::warn!(
+                "Skipping evaluation {} with invalid time in history-{jobset}",
+                entry.eval
+            );
+            continue;
+        };
+        points.push(Point {
+            eval: entry.eval,
+            time,
+            total: entry.failed,
+            direct: None,
+            indirect: None,
+        });
+    }
+    Ok(points)
+}
+
+/// Converts a time as shown by Hydra, like `2023-05-02 10:06:44 (UTC)`, to the format of the
+/// charts
+fn chart_time(time: &str) -> Option<String> {
+    let time = time.split(" (").next()?.trim();
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
@@ -0,0 +1,90 @@
+//! Root causes of indirect failures, as found by `most_important_deps`.
+//!
+//! `depcache` maps each build that failed because of a dependency to the builds the dependency
+//! failed in, and `mostimportantcache` names the store paths that failed in these builds. The
+//! other way around, `depcache` tells which builds a direct failure blocks.
+
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use zhf_common::cache::{cache_file, read_dep_cache, read_eval_cache, read_most_important_cache};
+
+/// A failed dependency of a build
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dependency {
+    /// Name of the store path that failed to build
+    pub name: String,
+    pub arch: String,
+    /// Build the dependency failed in
+    pub build_id: u64,
+}
+
+/// Failed dependencies of all indirect failures
+pub struct RootCauses {
+    by_build: HashMap<u64, BTreeSet<Dependency>>,
+    /// Builds that fail because of a build
+    blocked: HashMap<u64, BTreeSet<u64>>,
+    /// Attributes of all jobs
+    attrs: HashMap<u64, String>,
+}
+
+impl RootCauses {
+    /// Reads the root causes of some evaluations. Evaluations that were not crawled by
+    /// `most_important_deps` are skipped.
+    pub fn read(data_dir: &Path, evals: &[u64]) -> Result<Self> {
+        let mut by_build: HashMap<u64, BTreeSet<Dependency>> = HashMap::new();
+        let mut blocked: HashMap<u64, BTreeSet<u64>> = HashMap::new();
+        let mut attrs = HashMap::new();
+        for eval in evals {
+            for build in read_eval_cache(data_dir, *eval)? {
+                attrs.insert(build.build_id, build.attr);
+            }
+            if !cache_file(data_dir, "mostimportantcache", *eval).exists() {
+                log::warn!("No root causes found for evaluation {eval}");
+                continue;
+            }
+            // A build can fail in multiple store paths, and each of them is listed once for every
+            // build it blocks
+            let mut roots: HashMap<u64, BTreeSet<Dependency>> = HashMap::new();
+            for root in read_most_important_cache(data_dir, *eval)? {
+                roots.entry(root.build_id).or_default().insert(Dependency {
+                    name: root.name,
+                    arch: root.arch,
+                    build_id: root.build_id,
+                });
+            }
+            for entry in read_dep_cache(data_dir, *eval)? {
+                blocked
+                    .entry(entry.root_build_id)
+                    .or_default()
+                    .insert(entry.build_id);
+                if let Some(dependencies) = roots.get(&entry.root_build_id) {
+                    by_build
+                        .entry(entry.build_id)
+                        .or_default()
+                        .extend(dependencies.iter().cloned());
+                }
+            }
+        }
+        Ok(Self {
+            by_build,
+            blocked,
+            attrs,
+        })
+    }
+
+    /// Returns the failed dependencies of a build
+    pub fn of(&self, build_id: u64) -> impl Iterator<Item = &Dependency> {
+        self.by_build.get(&build_id).into_iter().flatten()
+    }
+
+    /// Returns the builds that fail because of a build
+    pub fn blocked_by(&self, build_id: u64) -> impl Iterator<Item = u64> + '_ {
+        self.blocked.get(&build_id).into_iter().flatten().copied()
+    }
+
+    /// Returns the attribute of a job
+    pub fn attr_of(&self, build_id: u64) -> Option<&str> {
+        self.attrs.get(&build_id).map(String::as_str)
+    }
+}
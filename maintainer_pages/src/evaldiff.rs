@@ -0,0 +1,155 @@
+//! The eval diff page, `public/failed/eval-diff.html`.
+//!
+//! Shows the reports of `zhf commits` on the two latest evaluations of each jobset, which are read
+//! from `evaldiff-linux.json` and `evaldiff-darwin.json`. Jobsets without a report have no previous
+//! evaluation to compare to.
+
+use crate::render::{DiffBroken, DiffCommit, DiffJobset, EvalDiffPage, Meta, Renderer};
+use anyhow::{anyhow, Result};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Jobsets with their titles
+const JOBSETS: &[(&str, &str)] = &[("linux", "Linux"), ("darwin", "Darwin")];
+
+/// A report of `zhf commits`
+#[derive(Deserialize)]
+struct Report {
+    old_eval: u64,
+    new_eval: u64,
+    old_revision: String,
+    new_revision: String,
+    commits: Vec<ReportCommit>,
+    newly_broken: Vec<ReportBroken>,
+}
+
+#[derive(Deserialize)]
+struct ReportCommit {
+    commit: String,
+    summary: String,
+    author: String,
+    time: i64,
+}
+
+#[derive(Deserialize)]
+struct ReportBroken {
+    attr: String,
+    system: String,
+    build_id: u64,
+    file: Option<String>,
+    line: Option<u64>,
+    suspects: Vec<String>,
+}
+
+/// Renders the eval diff page
+pub fn render(renderer: &Renderer, out: &Path, data_dir: &Path) -> Result<()> {
+    let jobsets = JOBSETS
+        .iter()
+        .map(|(jobset, title)| {
+            let loc = data_dir.join(format!("evaldiff-{jobset}.json"));
+            let Ok(json) = read_to_string(loc) else {
+                log::warn!("No eval diff of the {jobset} jobset found");
+                return Ok(DiffJobset {
+                    jobset: title.to_string(),
+                    has_diff: false,
+                    old_eval: 0,
+                    new_eval: 0,
+                    old_revision: String::new(),
+                    new_revision: String::new(),
+                    compare_url: String::new(),
+                    commit_count: 0,
+                    broken_count: 0,
+                    newly_broken: vec![],
+                    commits: vec![],
+                });
+            };
+            let report: Report = serde_json::from_str(&json)?;
+            jobset_diff(title, report)
+        })
+        .collect::<Result<_>>()?;
+    renderer.render(
+        &EvalDiffPage {
+            meta: Meta {
+                title: "Newly failing builds".to_string(),
+                heading: "Newly failing builds".to_string(),
+                og_title: "Newly failing builds".to_string(),
+                description:
+                    "Newly failing Hydra builds and the commits that could have broken them"
+                        .to_string(),
+                path: "failed/eval-diff.html".to_string(),
+                root: "../",
+            },
+            jobsets,
+        },
+        out,
+    )
+}
+
+fn jobset_diff(title: &str, report: Report) -> Result<DiffJobset> {
+    let commits = report
+        .commits
+        .into_iter()
+        .map(|commit| {
+            let time = Utc
+                .timestamp_opt(commit.time, 0)
+                .single()
+                .ok_or_else(|| anyhow!("Invalid time of commit {}", commit.commit))?;
+            Ok(DiffCommit {
+                short_commit: short(&commit.commit),
+                url: format!("https://github.com/NixOS/nixpkgs/commit/{}", commit.commit),
+                commit: commit.commit,
+                summary: commit.summary,
+                author: commit.author,
+                time: time.format("%Y-%m-%d %H:%M").to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let by_hash: HashMap<&str, &DiffCommit> = commits
+        .iter()
+        .map(|commit| (commit.commit.as_str(), commit))
+        .collect();
+    let newly_broken = report
+        .newly_broken
+        .into_iter()
+        .map(|broken| DiffBroken {
+            url: format!("https://hydra.nixos.org/build/{}", broken.build_id),
+            has_position: broken.file.is_some(),
+            position: match (&broken.file, broken.line) {
+                (Some(file), Some(line)) => format!("{file}:{line}"),
+                (Some(file), None) => file.clone(),
+                _ => String::new(),
+            },
+            suspects: broken
+                .suspects
+                .iter()
+                .filter_map(|hash| by_hash.get(hash.as_str()))
+                .map(|commit| (*commit).clone())
+                .collect(),
+            attr: broken.attr,
+            system: broken.system,
+        })
+        .collect::<Vec<_>>();
+    Ok(DiffJobset {
+        jobset: title.to_string(),
+        has_diff: true,
+        old_eval: report.old_eval,
+        new_eval: report.new_eval,
+        compare_url: format!(
+            "https://github.com/NixOS/nixpkgs/compare/{}...{}",
+            report.old_revision, report.new_revision
+        ),
+        old_revision: short(&report.old_revision),
+        new_revision: short(&report.new_revision),
+        commit_count: commits.len(),
+        broken_count: newly_broken.len(),
+        newly_broken,
+        commits,
+    })
+}
+
+fn short(commit: &str) -> String {
+    commit.chars().take(12).collect()
+}
@@ -0,0 +1,318 @@
+//! Atom feeds of builds that newly fail or got fixed, in `public/failed/feeds/`.
+//!
+//! The caches of previous evaluations are gone by the time the pages are rendered, so the failed
+//! builds of the last run are kept in `data/feedstate`, one build per line consisting of the
+//! evaluation, attribute and the comma-separated maintainers. Attributes are compared without the
+//! `nixpkgs.` prefix of the maintainers cache (see [`job_key`]), as the evaluation cache has none.
+//! Comparing against it yields the feed entries, which are kept in `data/feedhistory` for
+//! [`RETENTION_DAYS`] days. Each line there consists of the time the change was found, `failed` or
+//! `fixed`, the evaluation, attribute, build ID, job name, platform, maintainers and the status.
+//!
+//! Entry IDs only depend on the kind of the change, the attribute and the evaluation, so feed
+//! readers don't notify twice when the feeds are regenerated.
+
+use crate::handles::Handles;
+use crate::render::{FeedItem, FeedPage, Renderer};
+use crate::Groups;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{create_dir_all, read_to_string};
+use std::path::Path;
+use zhf_common::cache::{job_key, read_eval_cache};
+
+/// Number of days feed entries are kept for
+const RETENTION_DAYS: i64 = 30;
+/// Maximum number of entries in a single feed
+const MAX_ENTRIES: usize = 100;
+
+/// A failed build as stored in the feed state
+struct KnownFailure {
+    eval: u64,
+    maintainers: String,
+}
+
+/// A build that newly fails or got fixed
+pub struct Change {
+    time: DateTime<Utc>,
+    fixed: bool,
+    eval: u64,
+    attr: String,
+    build_id: u64,
+    name: String,
+    arch: String,
+    /// Comma-separated maintainers
+    maintainers: String,
+    status: String,
+}
+
+impl Change {
+    /// Maintainers of the build
+    pub fn maintainers(&self) -> impl Iterator<Item = &str> {
+        self.maintainers.split(',')
+    }
+}
+
+/// Compares the failed builds against the last run, stores the new state and returns all changes
+/// that are still kept
+pub fn update(
+    data_dir: &Path,
+    evals: &[u64],
+    maintainers: &Groups,
+    now: DateTime<Utc>,
+) -> Result<Vec<Change>> {
+    // Current failures with all their maintainers, by the key of their job
+    let mut current: BTreeMap<&str, (&crate::Build, BTreeSet<&str>)> = BTreeMap::new();
+    for (maintainer, builds) in maintainers {
+        for build in builds {
+            current
+                .entry(job_key(&build.attr))
+                .or_insert_with(|| (build, BTreeSet::new()))
+                .1
+                .insert(maintainer);
+        }
+    }
+
+    let state_file = data_dir.join("feedstate");
+    let history_file = data_dir.join("feedhistory");
+    let mut changes = match read_to_string(&history_file) {
+        Ok(history) => history
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(parse_change)
+            .collect::<Result<Vec<_>>>()?,
+        Err(_) => vec![],
+    };
+
+    if let Ok(state) = read_to_string(&state_file) {
+        let mut known = BTreeMap::new();
+        for line in state.lines().filter(|line| !line.is_empty()) {
+            let parts: Vec<&str> = line.split(' ').collect();
+            if parts.len() != 3 {
+                return Err(anyhow!("Invalid line in the feed state: {line}"));
+            }
+            known.insert(
+                job_key(parts[1]).to_string(),
+                KnownFailure {
+                    eval: parts[0].parse()?,
+                    maintainers: parts[2].to_string(),
+                },
+            );
+        }
+        let known_evals: HashSet<u64> = known.values().map(|failure| failure.eval).collect();
+        let new_evals: Vec<u64> = evals
+            .iter()
+            .copied()
+            .filter(|eval| !known_evals.contains(eval))
+            .collect();
+
+        // Builds of new evaluations that didn't fail before
+        for (key, (build, build_maintainers)) in &current {
+            if known.contains_key(*key) || !new_evals.contains(&build.eval) {
+                continue;
+            }
+            changes.push(Change {
+                time: now,
+                fixed: false,
+                eval: build.eval,
+                attr: build.attr.clone(),
+                build_id: build.build_id,
+                name: build.name.clone(),
+                arch: build.arch.clone(),
+                maintainers: join(build_maintainers),
+                status: build.status.clone(),
+            });
+        }
+        // Builds that failed before and succeed in a new evaluation. Jobs that are gone are
+        // not fixed.
+        for eval in &new_evals {
+            for build in read_eval_cache(data_dir, *eval)? {
+                let key = job_key(&build.attr);
+                if build.status != "Succeeded" || current.contains_key(key) {
+                    continue;
+                }
+                let Some(failure) = known.get(key) else {
+                    continue;
+                };
+                changes.push(Change {
+                    time: now,
+                    fixed: true,
+                    eval: *eval,
+                    attr: build.attr,
+                    build_id: build.build_id,
+                    name: build.name,
+                    arch: build.arch,
+                    maintainers: failure.maintainers.clone(),
+                    status: build.status,
+                });
+            }
+        }
+        log::info!(
+            "Found {} changes for the feeds",
+            changes.iter().filter(|change| change.time == now).count()
+        );
+    } else {
+        log::warn!("No feed state found, starting with empty feeds");
+    }
+
+    // Expire old entries, newest first
+    changes.retain(|change| now - change.time < Duration::days(RETENTION_DAYS));
+    changes.sort_by(|a, b| b.time.cmp(&a.time).then(a.attr.cmp(&b.attr)));
+
+    // Store everything
+    let mut history = String::new();
+    for change in &changes {
+        history.push_str(&format!(
+            "{} {} {} {} {} {} {} {} {}\n",
+            change.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            if change.fixed { "fixed" } else { "failed" },
+            change.eval,
+            change.attr,
+            change.build_id,
+            change.name,
+            change.arch,
+            change.maintainers,
+            change.status
+        ));
+    }
+    write_atomically(&history_file, &history)?;
+    let mut state = String::new();
+    for (key, (build, build_maintainers)) in &current {
+        state.push_str(&format!(
+            "{} {key} {}\n",
+            build.eval,
+            join(build_maintainers)
+        ));
+    }
+    write_atomically(&state_file, &state)?;
+
+    Ok(changes)
+}
+
+/// Renders the global feed and one feed for every maintainer
+pub fn render(
+    renderer: &Renderer,
+    out_dir: &Path,
+    changes: &[Change],
+    maintainers: &Groups,
+    handles: &Handles,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    create_dir_all(out_dir)?;
+    // Maintainers that were subscribed to keep their feed when they have no more failures
+    let mut names: BTreeSet<&str> = maintainers.keys().map(String::as_str).collect();
+    for change in changes {
+        names.extend(change.maintainers());
+    }
+
+    render_feed(
+        renderer,
+        &out_dir.join("all.atom"),
+        "all",
+        "All Hydra failures".to_string(),
+        "failed/all.html".to_string(),
+        changes.iter(),
+        now,
+    )?;
+    let mut rendered = 1;
+    for name in &names {
+        // Handles were validated when they were recorded, but the rules may have changed since
+        let Some(handle) = handles.get(name) else {
+            continue;
+        };
+        let slug = handle.slug();
+        render_feed(
+            renderer,
+            &out_dir.join(format!("{slug}.atom")),
+            slug,
+            format!(
+                "Hydra failures for packages maintained by {}",
+                handle.pretty_name()
+            ),
+            format!("failed/by-maintainer/{slug}.html"),
+            changes
+                .iter()
+                .filter(|change| change.maintainers().any(|m| m == *name)),
+            now,
+        )?;
+        rendered += 1;
+    }
+    log::info!("Rendered {rendered} feeds");
+    Ok(())
+}
+
+fn render_feed<'a>(
+    renderer: &Renderer,
+    out: &Path,
+    name: &str,
+    title: String,
+    page: String,
+    changes: impl Iterator<Item = &'a Change>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let entries: Vec<FeedItem> = changes
+        .take(MAX_ENTRIES)
+        .map(|change| {
+            let (kind, verb) = if change.fixed {
+                ("fixed", "was fixed")
+            } else {
+                ("failed", "fails")
+            };
+            FeedItem {
+                id: format!("tag:zh.fail,2023:{kind}/{}/{}", change.eval, change.attr),
+                title: format!("{} {verb} on {}", change.attr, change.arch),
+                url: format!("https://hydra.nixos.org/build/{}", change.build_id),
+                updated: change.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                summary: format!(
+                    "{} ({}) {verb} in evaluation {} with status \"{}\"",
+                    change.name, change.arch, change.eval, change.status
+                ),
+            }
+        })
+        .collect();
+    let updated = entries.first().map_or_else(
+        || now.to_rfc3339_opts(SecondsFormat::Secs, true),
+        |entry| entry.updated.clone(),
+    );
+    renderer.render(
+        &FeedPage {
+            id: format!("https://zh.fail/failed/feeds/{name}.atom"),
+            title,
+            page_url: format!("https://zh.fail/{page}"),
+            updated,
+            entries,
+        },
+        out,
+    )
+}
+
+fn parse_change(line: &str) -> Result<Change> {
+    let parts: Vec<&str> = line.splitn(9, ' ').collect();
+    if parts.len() != 9 {
+        return Err(anyhow!("Invalid line in the feed history: {line}"));
+    }
+    Ok(Change {
+        time: DateTime::parse_from_rfc3339(parts[0])?.with_timezone(&Utc),
+        fixed: parts[1] == "fixed",
+        eval: parts[2].parse()?,
+        attr: parts[3].to_string(),
+        build_id: parts[4].parse()?,
+        name: parts[5].to_string(),
+        arch: parts[6].to_string(),
+        maintainers: parts[7].to_string(),
+        status: parts[8].to_string(),
+    })
+}
+
+fn join(maintainers: &BTreeSet<&str>) -> String {
+    maintainers.iter().copied().collect::<Vec<_>>().join(",")
+}
+
+/// Writes to a temporary file first so aborted runs don't leave half-written files
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(".new");
+    std::fs::write(&new_path, contents)?;
+    std::fs::rename(new_path, path)?;
+    Ok(())
+}
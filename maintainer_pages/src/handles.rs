@@ -0,0 +1,162 @@
+//! Validation of maintainer and team handles.
+//!
+//! Handles end up in file names and URLs, so they are checked before anything is rendered.
+//! Builds of rejected handles are attributed to nobody unless they have another maintainer, and
+//! the rejected handles are listed in `rejected.html`.
+//!
+//! The slug of a handle is the handle itself, unless it collides with another handle on a
+//! case-insensitive filesystem or with a reserved file name. In that case, a number is appended
+//! to all but the first of the colliding handles in sorted order, which keeps the slugs stable
+//! between runs.
+
+use crate::Groups;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Handle of builds without maintainer
+pub const NOBODY: &str = "_";
+/// Maximum length of a handle. GitHub allows 39 characters, teams are a bit longer.
+const MAX_LEN: usize = 64;
+/// Slugs that are used by other files in the same directory
+const RESERVED: &[&str] = &["all"];
+
+/// A handle that is safe to use in file names
+pub struct MaintainerHandle {
+    name: String,
+    slug: String,
+}
+
+impl MaintainerHandle {
+    /// Checks whether a handle is valid, returning the reason if it's not
+    pub fn validate(name: &str) -> Result<(), &'static str> {
+        if name == NOBODY {
+            return Ok(());
+        }
+        if name.is_empty() {
+            return Err("Empty handle");
+        }
+        if name.len() > MAX_LEN {
+            return Err("Handle is too long");
+        }
+        if name.starts_with('-') {
+            return Err("Handle starts with a hyphen");
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err("Handle contains invalid characters");
+        }
+        Ok(())
+    }
+
+    /// The handle as it appears in nixpkgs
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// File name of the handle without extension
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    /// Name for titles
+    pub fn pretty_name(&self) -> &str {
+        if self.name == NOBODY {
+            "nobody"
+        } else {
+            &self.name
+        }
+    }
+}
+
+/// Slugs of all valid handles
+pub struct Handles {
+    handles: HashMap<String, MaintainerHandle>,
+}
+
+impl Handles {
+    /// Assigns slugs to all handles. Invalid handles are skipped.
+    pub fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let names: BTreeSet<&str> = names
+            .into_iter()
+            .filter(|name| MaintainerHandle::validate(name).is_ok())
+            .collect();
+        let mut taken: HashSet<String> = RESERVED.iter().map(|slug| slug.to_string()).collect();
+        let mut handles = HashMap::new();
+        for name in names {
+            let mut slug = name.to_string();
+            let mut n = 1;
+            while !taken.insert(slug.to_lowercase()) {
+                n += 1;
+                slug = format!("{name}-{n}");
+            }
+            if n > 1 {
+                log::warn!("Handle {name} collides with another handle, using {slug}");
+            }
+            handles.insert(
+                name.to_string(),
+                MaintainerHandle {
+                    name: name.to_string(),
+                    slug,
+                },
+            );
+        }
+        Self { handles }
+    }
+
+    /// Returns the handle of a name, if it's valid
+    pub fn get(&self, name: &str) -> Option<&MaintainerHandle> {
+        self.handles.get(name)
+    }
+}
+
+/// A handle that was rejected
+pub struct Rejected {
+    pub name: String,
+    pub reason: &'static str,
+    /// Number of failed builds of the handle
+    pub builds: usize,
+}
+
+/// Removes all groups with invalid handles. Their builds that don't belong to any other group
+/// are moved to [`NOBODY`] if `to_nobody` is set.
+pub fn reject_invalid(groups: &mut Groups, to_nobody: bool) -> Vec<Rejected> {
+    let invalid: Vec<(String, &'static str)> = groups
+        .keys()
+        .filter_map(|name| {
+            MaintainerHandle::validate(name)
+                .err()
+                .map(|reason| (name.clone(), reason))
+        })
+        .collect();
+    let mut rejected = vec![];
+    let mut orphans = vec![];
+    for (name, reason) in invalid {
+        let builds = groups.remove(&name).unwrap_or_default();
+        log::warn!(
+            "Rejecting handle {name:?} with {} builds: {reason}",
+            builds.len()
+        );
+        rejected.push(Rejected {
+            name,
+            reason,
+            builds: builds.len(),
+        });
+        orphans.extend(builds);
+    }
+    if to_nobody && !orphans.is_empty() {
+        let mut known: HashSet<String> = groups
+            .values()
+            .flatten()
+            .map(|build| build.attr.clone())
+            .collect();
+        for mut build in orphans {
+            if known.insert(build.attr.clone()) {
+                build.maintainer = NOBODY.to_string();
+                groups.entry(NOBODY.to_string()).or_default().push(build);
+            }
+        }
+    }
+    rejected.sort_by(|a, b| a.name.cmp(&b.name));
+    rejected
+}
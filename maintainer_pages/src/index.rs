@@ -0,0 +1,307 @@
+//! The landing page, `public/index.html`.
+//!
+//! Besides the failed builds, the page shows the merges of staging-next from `staging-history`,
+//! their impact from `stagingimpact.json` (see `zhf staging`) and the ranking of
+//! `most_important_deps` from `mostproblematicdeps.json`. The data of the charts is
+//! embedded as JSON and drawn by `js/index.js`, which loads the history of the failure counts from
+//! `data/burndown.json` (see [`crate::burndown`]).
+
+use crate::api::read_evals;
+use crate::platforms::Platforms;
+use crate::render::{
+    CategoryCount, EvalCell, ImpactBroken, ImpactEval, ImpactSystem, IndexPage, ProblematicBlocked,
+    ProblematicDep, ProblematicPlatform, Renderer, StagingImpact, SystemCount,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Everything on the landing page that doesn't come from the data directory
+pub struct Inputs {
+    pub target_branch: String,
+    /// What started the run, like the pipeline source of the CI
+    pub triggered_by: String,
+    /// Latest evaluation of the Linux jobset
+    pub linux_eval: u64,
+    /// Latest evaluation of the Darwin jobset
+    pub darwin_eval: u64,
+    /// Platforms whose page was rendered
+    pub platform_pages: BTreeSet<String>,
+}
+
+/// Data of the charts
+#[derive(Serialize)]
+struct ChartData<'a> {
+    staging_merges: Vec<StagingMerge>,
+    categories: &'a [CategoryCount],
+}
+
+/// A merge of staging-next into the target branch
+#[derive(Serialize)]
+struct StagingMerge {
+    commit: String,
+    time: String,
+}
+
+/// The parts of `mostproblematicdeps.json` that are shown
+#[derive(Deserialize)]
+struct Ranking {
+    root_causes: Vec<RankedRootCause>,
+}
+
+#[derive(Deserialize)]
+struct RankedRootCause {
+    name: String,
+    dependants: usize,
+    platforms: Vec<RankedPlatform>,
+    blocked: Vec<RankedBlocked>,
+}
+
+#[derive(Deserialize)]
+struct RankedPlatform {
+    system: String,
+    build_id: u64,
+    dependants: usize,
+}
+
+#[derive(Deserialize)]
+struct RankedBlocked {
+    attr: String,
+    build_id: u64,
+    also_blocked_by: Vec<String>,
+}
+
+/// The merges of `stagingimpact.json`
+#[derive(Deserialize)]
+struct Impacts {
+    merges: Vec<Impact>,
+}
+
+#[derive(Deserialize)]
+struct Impact {
+    commit: String,
+    time: i64,
+    evals: Vec<ImpactEvalPair>,
+    systems: Vec<ImpactDelta>,
+    newly_broken: Vec<ImpactBrokenAttr>,
+}
+
+#[derive(Deserialize)]
+struct ImpactEvalPair {
+    jobset: String,
+    before: Option<u64>,
+    after: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ImpactDelta {
+    system: String,
+    before: usize,
+    after: usize,
+}
+
+#[derive(Deserialize)]
+struct ImpactBrokenAttr {
+    attr: String,
+    build_id: u64,
+}
+
+/// Renders the landing page
+pub fn render(
+    renderer: &Renderer,
+    out: &Path,
+    data_dir: &Path,
+    inputs: &Inputs,
+    platforms: &Platforms,
+    categories: &HashMap<u64, String>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let evals = read_evals(data_dir, &[inputs.linux_eval, inputs.darwin_eval]);
+    let [linux_eval, darwin_eval] = [&evals[0], &evals[1]].map(|eval| EvalCell {
+        id: eval.id,
+        time: eval.time.clone().unwrap_or_else(|| "unknown".to_string()),
+    });
+
+    let systems: Vec<_> = platforms
+        .iter()
+        .map(|(system, builds)| SystemCount {
+            system: system.clone(),
+            count: builds.len(),
+            has_page: inputs.platform_pages.contains(system),
+        })
+        .collect();
+
+    // Count the same builds as above, so both add up
+    let mut category_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for build in platforms.values().flatten() {
+        if let Some(category) = categories.get(&build.build_id) {
+            *category_counts.entry(category).or_default() += 1;
+        }
+    }
+    let mut category_counts: Vec<_> = category_counts
+        .into_iter()
+        .map(|(name, count)| CategoryCount {
+            name: name.to_string(),
+            count,
+        })
+        .collect();
+    // Stable sort, so categories with the same count stay alphabetical
+    category_counts.sort_by_key(|category| std::cmp::Reverse(category.count));
+
+    let chart_data = ChartData {
+        staging_merges: read_staging_merges(data_dir)?,
+        categories: &category_counts,
+    };
+
+    let page = IndexPage {
+        target_branch: inputs.target_branch.clone(),
+        last_check: now.format("%Y-%m-%d %H:%M:%S (UTC)").to_string(),
+        triggered_by: inputs.triggered_by.clone(),
+        linux_eval,
+        darwin_eval,
+        total: systems.iter().map(|system| system.count).sum(),
+        systems,
+        chart_data: script_json(&chart_data)?,
+        categories: category_counts,
+        problematic: read_problematic_deps(data_dir)?,
+        staging_impacts: read_staging_impacts(data_dir)?,
+    };
+    renderer.render(&page, out)
+}
+
+/// Reads the merges of staging-next, which are lines of the commit and the commit time
+fn read_staging_merges(data_dir: &Path) -> Result<Vec<StagingMerge>> {
+    let Ok(lines) = read_to_string(data_dir.join("staging-history")) else {
+        log::warn!("No staging history found");
+        return Ok(vec![]);
+    };
+    let mut merges = vec![];
+    for line in lines.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let (commit, time) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid line in staging-history: {line}"))?;
+        let time = Utc
+            .timestamp_opt(time.parse::<i64>()?, 0)
+            .single()
+            .ok_or_else(|| anyhow!("Invalid time in staging-history: {line}"))?;
+        merges.push(StagingMerge {
+            commit: commit.to_string(),
+            time: time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        });
+    }
+    Ok(merges)
+}
+
+/// Reads the impact of the recent merges of staging-next
+fn read_staging_impacts(data_dir: &Path) -> Result<Vec<StagingImpact>> {
+    let Ok(json) = read_to_string(data_dir.join("stagingimpact.json")) else {
+        log::warn!("No impact of staging merges found");
+        return Ok(vec![]);
+    };
+    let impacts: Impacts = serde_json::from_str(&json)?;
+    impacts
+        .merges
+        .into_iter()
+        .map(|merge| {
+            let time = Utc
+                .timestamp_opt(merge.time, 0)
+                .single()
+                .ok_or_else(|| anyhow!("Invalid time of staging merge {}", merge.commit))?;
+            Ok(StagingImpact {
+                short_commit: merge.commit.chars().take(12).collect(),
+                url: format!("https://github.com/NixOS/nixpkgs/commit/{}", merge.commit),
+                commit: merge.commit,
+                time: time.format("%Y-%m-%d %H:%M (UTC)").to_string(),
+                evals: merge
+                    .evals
+                    .into_iter()
+                    .map(|eval| ImpactEval {
+                        jobset: eval.jobset,
+                        has_before: eval.before.is_some(),
+                        before: eval.before.unwrap_or_default(),
+                        has_after: eval.after.is_some(),
+                        after: eval.after.unwrap_or_default(),
+                    })
+                    .collect(),
+                systems: merge
+                    .systems
+                    .into_iter()
+                    .map(|system| ImpactSystem {
+                        delta: format!("{:+}", system.after as i64 - system.before as i64),
+                        system: system.system,
+                        before: system.before,
+                        after: system.after,
+                    })
+                    .collect(),
+                broken_count: merge.newly_broken.len(),
+                newly_broken: merge
+                    .newly_broken
+                    .into_iter()
+                    .map(|broken| ImpactBroken {
+                        attr: broken.attr,
+                        url: build_url(broken.build_id),
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Reads the ranking of the dependencies that block the most builds
+fn read_problematic_deps(data_dir: &Path) -> Result<Vec<ProblematicDep>> {
+    let Ok(json) = read_to_string(data_dir.join("mostproblematicdeps.json")) else {
+        log::warn!("No ranking of problematic dependencies found");
+        return Ok(vec![]);
+    };
+    let ranking: Ranking = serde_json::from_str(&json)?;
+    Ok(ranking
+        .root_causes
+        .into_iter()
+        .map(|root_cause| ProblematicDep {
+            url: root_cause
+                .platforms
+                .first()
+                .map(|platform| build_url(platform.build_id))
+                .unwrap_or_default(),
+            name: root_cause.name,
+            dependants: root_cause.dependants,
+            platforms: root_cause
+                .platforms
+                .into_iter()
+                .map(|platform| ProblematicPlatform {
+                    url: build_url(platform.build_id),
+                    system: platform.system,
+                    dependants: platform.dependants,
+                })
+                .collect(),
+            blocked: root_cause
+                .blocked
+                .into_iter()
+                .map(|blocked| ProblematicBlocked {
+                    url: build_url(blocked.build_id),
+                    attr: blocked.attr,
+                    also_blocked_by: blocked.also_blocked_by.join(", "),
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+fn build_url(build_id: u64) -> String {
+    format!("https://hydra.nixos.org/build/{build_id}")
+}
+
+/// Serializes a value as JSON that can be embedded in a script element. `<`, `>` and `&` only
+/// occur in strings, where they can be replaced by their escape sequences.
+fn script_json<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(value)?
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026"))
+}
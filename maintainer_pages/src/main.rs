@@ -1,8 +1,32 @@
-//! Renders the per-maintainer pages and overviews
-use anyhow::Result;
-use std::collections::HashMap;
-use std::fs::{create_dir_all, read_to_string, File};
-use std::io::Write as _;
+//! Renders the landing page, the per-maintainer pages and overviews
+mod api;
+mod burndown;
+mod deps;
+mod evaldiff;
+mod feeds;
+mod handles;
+mod index;
+mod platforms;
+mod render;
+mod search;
+mod sources;
+mod teams;
+
+use anyhow::{anyhow, Result};
+use deps::RootCauses;
+use handles::{Handles, MaintainerHandle};
+use render::{
+    AllPage, BlockedCell, BlockingEntry, DependencyCell, MaintainerPage, Meta, OverviewEntry,
+    OverviewPage, RejectedEntry, RejectedPage, Renderer, Row, Section,
+};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+use zhf_common::cache::{
+    data_dir, log_file, read_class_cache, read_eval_cache, read_eval_revisions, read_failed_builds,
+    read_position_cache,
+};
 
 struct Build {
     attr: String,
@@ -11,20 +35,59 @@ struct Build {
     arch: String,
     status: String,
     maintainer: String,
+    /// Evaluation the build belongs to
+    eval: u64,
+}
+
+/// Everything known about failed builds besides the maintainers cache
+struct Details {
+    /// Failure categories of direct failures
+    categories: HashMap<u64, String>,
+    /// Log excerpts of direct failures
+    logs: HashMap<u64, String>,
+    /// Where failed builds are defined in nixpkgs, at the revision of their evaluation
+    positions: HashMap<u64, SourcePosition>,
+    root_causes: RootCauses,
 }
 
+/// File and line of `meta.position` of a build
+struct SourcePosition {
+    file: String,
+    line: u64,
+    /// Link to the line at the nixpkgs revision of the evaluation
+    url: String,
+}
+
+/// Builds grouped by maintainer or team
+type Groups = HashMap<String, Vec<Build>>;
+
 fn main() -> Result<()> {
     env_logger::builder().format_timestamp(None).init();
     // Handle args
-    let argv: Vec<u64> = std::env::args()
-        .skip(1)
-        .map(|x| x.parse::<u64>().unwrap())
-        .collect();
-    log::info!("Will generate evaluations: {:?}", argv);
+    let mut evals: Vec<u64> = Vec::new();
+    let mut templates = None;
+    let mut target_branch = "master".to_string();
+    let mut linux_eval = None;
+    let mut darwin_eval = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{arg} needs a value"));
+        match arg.as_str() {
+            "--templates" => templates = Some(PathBuf::from(value()?)),
+            "--target-branch" => target_branch = value()?,
+            "--linux-eval" => linux_eval = Some(value()?.parse::<u64>()?),
+            "--darwin-eval" => darwin_eval = Some(value()?.parse::<u64>()?),
+            _ => evals.push(arg.parse::<u64>()?),
+        }
+    }
+    log::info!("Will generate evaluations: {:?}", evals);
+    if let Some(dir) = &templates {
+        log::info!("Using templates from {}", dir.display());
+    }
+    let renderer = Renderer::new(templates);
 
     // Prepare directories
-    let mut data_dir = std::env::current_dir()?;
-    data_dir.push("data");
+    let data_dir = data_dir()?;
     let mut maintainers_cache = data_dir.clone();
     maintainers_cache.push("maintainerscache");
     let mut out_dir = std::env::current_dir()?;
@@ -34,133 +97,83 @@ fn main() -> Result<()> {
     create_dir_all(&out_dir)?;
 
     // Read the cache
-    let mut maintainers: HashMap<String, Vec<Build>> = HashMap::new();
-    for eval in argv {
+    let mut maintainers = Groups::new();
+    let mut categories = HashMap::new();
+    for eval in &evals {
+        let eval = *eval;
+        categories.extend(read_class_cache(&data_dir, eval)?);
         // Read maintainers cache
         let mut cache_loc = maintainers_cache.clone();
         cache_loc.push(format!("{eval}.cache"));
-        let lines = read_to_string(cache_loc)?;
-        let lines: Vec<&str> = lines.split('\n').collect();
-        for line in lines {
-            if line.is_empty() {
+        read_grouped(&cache_loc, eval, &mut maintainers)?;
+    }
+    let mut rejected = handles::reject_invalid(&mut maintainers, true);
+    retain_failed(&mut maintainers);
+    let (teams, team_builds) = teams::read(&data_dir, &evals, &mut rejected)?;
+
+    // Load log excerpts of direct failures
+    let mut logs = HashMap::new();
+    for builds in maintainers.values().chain(team_builds.values()) {
+        for build in builds {
+            if build.status == "Dependency failed" || logs.contains_key(&build.build_id) {
                 continue;
             }
-            let parts: Vec<&str> = line.splitn(6, ' ').collect();
-            // Group by maintainer
-            let maintainer = parts[0].to_string();
-            let build = Build {
-                attr: parts[1].to_string(),
-                build_id: parts[2].parse::<u64>()?,
-                name: parts[3].to_string(),
-                arch: parts[4].to_string(),
-                status: parts[5].to_string(),
-                maintainer: maintainer.to_string(),
-            };
-            if let Some(entry) = maintainers.get_mut(&maintainer) {
-                entry.push(build);
-            } else {
-                maintainers.insert(maintainer, vec![build]);
+            if let Ok(log) = read_to_string(log_file(&data_dir, build.build_id)) {
+                logs.insert(build.build_id, log);
             }
         }
     }
+    log::info!("Loaded {} log excerpts", logs.len());
+    let details = Details {
+        categories,
+        logs,
+        positions: read_positions(&data_dir, &evals)?,
+        root_causes: RootCauses::read(&data_dir, &evals)?,
+    };
 
-    // Sort builds
-    for builds in maintainers.values_mut() {
-        builds.sort_by(|a, b| a.attr.partial_cmp(&b.attr).unwrap());
-    }
-    // Filter out successful builds
-    for builds in maintainers.values_mut() {
-        builds.retain(|x| x.status != "Succeeded");
-    }
-    // Filter out maintainers without failures
-    maintainers.retain(|_, x| !x.is_empty());
+    // Find the changes for the feeds before assigning slugs, so maintainers without failures
+    // keep their feed
+    let now = chrono::Utc::now();
+    let changes = feeds::update(&data_dir, &evals, &maintainers, now)?;
+    let handles = Handles::new(
+        maintainers
+            .keys()
+            .map(String::as_str)
+            .chain(changes.iter().flat_map(feeds::Change::maintainers)),
+    );
 
     // For all.html
     let mut all_failed_builds = HashMap::new();
 
     // Render per-maintainer pages
     for (maintainer_name, builds) in &maintainers {
-        // Pretty name for titles
-        let pretty_name = if maintainer_name == "_" {
-            "nobody".to_string()
-        } else {
-            maintainer_name.clone()
-        };
-
-        let mut out = out_dir.clone();
-        out.push(format!("{maintainer_name}.html"));
-        let mut out = File::create(out)?;
-        // Write top part
-        out.write_fmt(format_args!(r#"<!DOCTYPE html>
-        <html lang="en">
-          <head>
-            <meta charset="UTF-8">
-            <meta name="viewport" content="width=device-width, initial-scale=1.0">
-            <meta http-equiv="X-UA-Compatible" content="ie=edge">
-            <title>Hydra failures ({pretty_name})</title>
-            <link rel="stylesheet" href="../../style.css">
-            <link rel="icon" type="image/x-icon" href="../../favicon.ico">
-            <meta property="og:title" content="Per-maintainer Hydra failures" />
-            <meta property="og:description" content="Track Hydra failures that have {pretty_name} as their maintainer" />
-            <meta property="og:type" content="website" />
-            <meta property="og:url" content="https://zh.fail/failed/by-maintainer/{maintainer_name}.html" />
-            <meta property="og:image" content="../../icon.png" />
-          </head>
-          <body id="maintainer-body">
-            <h1><a href="../../index.html" title="Go Home"><img src="../../nix-snowflake.svg"></a>Hydra failures for packages maintained by {pretty_name}</h1>
-            <p>Jump to: <a href='#direct'>Direct Failures</a>&nbsp;&bull;&nbsp;<a href='#indirect'>Indirect Failures</a></p>
-            <h2 id="direct">Direct failures</h2>
-            <p>These are packages fail to build themselves.</p>
-            <table>
-              <thead><tr><th>Attribute</th><th>Job name</th><th>Platform</th><th>Result</th></th></thead>
-              <tbody>"#))?;
-        // Table for direct failures
-        let mut found = false;
+        let handle = handle(&handles, maintainer_name)?;
+        let pretty_name = handle.pretty_name();
+        let slug = handle.slug();
         for build in builds {
             // Propagate list for all.html
             all_failed_builds.insert(build.attr.clone(), build);
-
-            if build.status == "Dependency failed" {
-                continue;
-            }
-            found = true;
-            out.write_fmt(format_args!("<tr><td><a href=\"https://hydra.nixos.org/build/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>", build.build_id, build.attr, build.name, build.arch, build.status))?;
-        }
-        if !found {
-            out.write_fmt(format_args!(
-                r#"<tr><td colspan="4" class="none">None 🎉</td></tr>"#
-            ))?;
-        }
-        // Middle between the two tables
-        out.write_fmt(format_args!(r#"</tbody>
-        </table>
-        <p>Jump to: <a href='#direct'>Direct Failures</a>&nbsp;&bull;&nbsp;<a href='#indirect'>Indirect Failures</a></p>
-        <h2 id="indirect">Indirect failures</h2>
-        <p>These are packages where a dependency failed to build.<br></p>
-        <table>
-          <thead><tr><th>Attribute</th><th>Job name</th><th>Platform</th><th>Result</th></th></thead>
-          <tbody>"#))?;
-        // Table for indirect failures
-        let mut found = false;
-        for build in builds {
-            if build.status != "Dependency failed" {
-                continue;
-            }
-            found = true;
-            out.write_fmt(format_args!("<tr><td><a href=\"https://hydra.nixos.org/build/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>", build.build_id, build.attr, build.name, build.arch, build.status))?;
-        }
-        if !found {
-            out.write_fmt(format_args!(
-                r#"<tr><td colspan="4" class="none">None 🎉</td></tr>"#
-            ))?;
         }
-        // Bottom
-        out.write_fmt(format_args!(
-            r#"</tbody>
-            </table>
-          </body>
-        </html>"#
-        ))?;
+
+        let page = MaintainerPage {
+            meta: Meta {
+                title: format!("Hydra failures ({pretty_name})"),
+                heading: format!("Hydra failures for packages maintained by {pretty_name}"),
+                og_title: "Per-maintainer Hydra failures".to_string(),
+                description: format!(
+                    "Track Hydra failures that have {pretty_name} as their maintainer"
+                ),
+                path: format!("failed/by-maintainer/{slug}.html"),
+                root: "../../",
+            },
+            name: handle.name().to_string(),
+            feed: format!("{slug}.atom"),
+            blocking: blocking(builds, &details.root_causes),
+            sections: sections(builds.iter(), false, &details),
+        };
+        let mut out = out_dir.clone();
+        out.push(format!("{slug}.html"));
+        renderer.render(&page, &out)?;
     }
 
     // Render overview over all maintainers
@@ -169,104 +182,369 @@ fn main() -> Result<()> {
     let mut failed_dir = std::env::current_dir()?;
     failed_dir.push("public");
     failed_dir.push("failed");
-    let mut out = failed_dir.clone();
-    out.push("overview.html");
-    let mut out = File::create(out)?;
-    out.write_fmt(format_args!(r#"<!DOCTYPE html>
-    <html lang="en">
-      <head>
-        <meta charset="UTF-8">
-        <meta name="viewport" content="width=device-width, initial-scale=1.0">
-        <meta http-equiv="X-UA-Compatible" content="ie=edge">
-        <title>Hydra failures by maintainer</title>
-        <link rel="stylesheet" href="../style.css">
-        <link rel="icon" type="image/x-icon" href="../favicon.ico">
-        <meta property="og:title" content="Hydra failures by maintainer" />
-        <meta property="og:description" content="Overview of maintainers of broken Hydra packages" />
-        <meta property="og:type" content="website" />
-        <meta property="og:url" content="https://zh.fail/failed/overview.html" />
-        <meta property="og:image" content="../icon.png" />
-      </head>
-      <body id="maintainer-overview">
-        <h1><a href="../index.html" title="Go Home"><img src="../nix-snowflake.svg"></a>Hydra failures by maintainer</h1>
-        <p>If your name is not in this list, then you don't maintain any failed packages. Congratulations!</p>
-        <ul>"#))?;
-    for maintainer_name in maintainer_names {
-        let num_failed = &maintainers.get(maintainer_name).unwrap().len();
-        out.write_fmt(format_args!("<li><a href='by-maintainer/{maintainer_name}.html'>{maintainer_name}</a> ({num_failed})</li>"))?;
+    let mut entries = maintainer_names
+        .into_iter()
+        .map(|name| {
+            let handle = handle(&handles, name)?;
+            let builds = &maintainers[name];
+            // Distinct builds that are blocked by any of the failures
+            let blocked: HashSet<u64> = builds
+                .iter()
+                .filter(|build| build.status != "Dependency failed")
+                .flat_map(|build| details.root_causes.blocked_by(build.build_id))
+                .collect();
+            Ok(OverviewEntry {
+                name: handle.name().to_string(),
+                slug: handle.slug().to_string(),
+                failed: builds.len(),
+                blocking: blocked.len(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for by_blocked in [false, true] {
+        let file_name = if by_blocked {
+            // Stable sort, so maintainers with the same blast radius stay alphabetical
+            entries.sort_by_key(|entry| Reverse(entry.blocking));
+            "overview-by-blocked.html"
+        } else {
+            "overview.html"
+        };
+        let page = OverviewPage {
+            meta: Meta {
+                title: "Hydra failures by maintainer".to_string(),
+                heading: "Hydra failures by maintainer".to_string(),
+                og_title: "Hydra failures by maintainer".to_string(),
+                description: "Overview of maintainers of broken Hydra packages".to_string(),
+                path: format!("failed/{file_name}"),
+                root: "../",
+            },
+            maintainers: entries,
+            by_blocked,
+            rejected: rejected.len(),
+        };
+        let mut out = failed_dir.clone();
+        out.push(file_name);
+        renderer.render(&page, &out)?;
+        entries = page.maintainers;
     }
-    out.write_fmt(format_args!("</ul></body></html>"))?;
+
+    // Render the report of rejected handles
+    let page = RejectedPage {
+        meta: Meta {
+            title: "Rejected maintainers".to_string(),
+            heading: "Rejected maintainers".to_string(),
+            og_title: "Rejected maintainers".to_string(),
+            description: "Maintainer and team handles that can't be used for pages".to_string(),
+            path: "failed/rejected.html".to_string(),
+            root: "../",
+        },
+        rejected: rejected
+            .iter()
+            .map(|rejected| RejectedEntry {
+                name: rejected.name.clone(),
+                reason: rejected.reason,
+                builds: rejected.builds,
+            })
+            .collect(),
+    };
+    let mut out = failed_dir.clone();
+    out.push("rejected.html");
+    renderer.render(&page, &out)?;
 
     // Render the overview over all failed builds
     let mut all_attrs: Vec<_> = all_failed_builds.keys().collect();
     all_attrs.sort();
+    let page = AllPage {
+        meta: Meta {
+            title: "All Hydra failures".to_string(),
+            heading: "All Hydra failures".to_string(),
+            og_title: "All Hydra failures".to_string(),
+            description: "Overview of all Hydra failures of the most recent evaluations"
+                .to_string(),
+            path: "failed/all.html".to_string(),
+            root: "../",
+        },
+        sections: link_logs(
+            sections(
+                all_attrs.iter().map(|attr| all_failed_builds[*attr]),
+                true,
+                &details,
+            ),
+            &handles,
+        )?,
+    };
     let mut out = failed_dir.clone();
     out.push("all.html");
-    let mut out = File::create(out)?;
-    out.write_fmt(format_args!(r#"<!DOCTYPE html>
-    <html lang="en">
-      <head>
-        <meta charset="UTF-8">
-        <meta name="viewport" content="width=device-width, initial-scale=1.0">
-        <meta http-equiv="X-UA-Compatible" content="ie=edge">
-        <title>All Hydra failures</title>
-        <link rel="stylesheet" href="../style.css">
-        <link rel="icon" type="image/x-icon" href="../favicon.ico">
-        <meta property="og:title" content="All Hydra failures" />
-        <meta property="og:description" content="Overview of all Hydra failures of the most recent evaluations" />
-        <meta property="og:type" content="website" />
-        <meta property="og:url" content="https://zh.fail/failed/all.html" />
-        <meta property="og:image" content="../icon.png" />
-      </head>
-      <body>
-        <h1><a href="../index.html" title="Go Home"><img src="../nix-snowflake.svg"></a>All Hydra failures</h1>
-        <p>Jump to: <a href='#direct'>Direct Failures</a>&nbsp;&bull;&nbsp;<a href='#indirect'>Indirect Failures</a></p>
-        <h2 id="direct">Direct failures</h2>
-        <p>These are packages fail to build themselves.</p>
-        <table>
-            <thead><tr><th>Attribute</th><th>Job name</th><th>Platform</th><th>Maintainer</th><th>Result</th></th></thead>
-            <tbody>"#))?;
-    // Direct failures
-    let mut found = false;
-    for attr in &all_attrs {
-        let build = &all_failed_builds.get(*attr).unwrap();
-        if build.status == "Dependency failed" {
-            continue;
+    renderer.render(&page, &out)?;
+
+    // Render the platform pages
+    let platforms = platforms::collect(&data_dir, &evals, &maintainers)?;
+    let platform_pages = platforms::render(&renderer, &failed_dir, &platforms, &details)?;
+
+    // Render the team pages
+    teams::render(&renderer, &failed_dir, &teams, &team_builds, &details)?;
+
+    // Render the feeds
+    feeds::render(
+        &renderer,
+        &failed_dir.join("feeds"),
+        &changes,
+        &maintainers,
+        &handles,
+        now,
+    )?;
+
+    // Render the failed source downloads
+    let source_failures = sources::collect(&data_dir, &evals, &details.categories)?;
+    log::info!("Found {} failed source downloads", source_failures.len());
+    let mut out = failed_dir.clone();
+    out.push("sources.html");
+    sources::render(&renderer, &out, &source_failures)?;
+
+    // Render the newly failing builds and the commits that could have broken them
+    let mut out = failed_dir.clone();
+    out.push("eval-diff.html");
+    evaldiff::render(&renderer, &out, &data_dir)?;
+
+    // Write the machine-readable versions
+    let mut api_dir = std::env::current_dir()?;
+    api_dir.push("public");
+    api_dir.push("api");
+    api_dir.push("v1");
+    let generated_at = now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let all_builds: Vec<_> = all_attrs
+        .iter()
+        .map(|attr| all_failed_builds[*attr])
+        .collect();
+    search::write(
+        &failed_dir.join("search-index.json"),
+        &maintainers,
+        &all_builds,
+        &details.categories,
+    )?;
+    let api_evals = api::read_evals(&data_dir, &evals);
+    api::write(
+        &api_dir,
+        &generated_at,
+        &api_evals,
+        &maintainers,
+        &handles,
+        &all_builds,
+        &details.categories,
+    )?;
+    api::write_summary(
+        &api_dir,
+        &generated_at,
+        &api_evals,
+        &read_failed_builds(&data_dir, &evals)?,
+    )?;
+
+    // Render the landing page and its charts, which need to know which jobset each evaluation
+    // belongs to
+    if let (Some(linux_eval), Some(darwin_eval)) = (linux_eval, darwin_eval) {
+        let jobset_evals = api::read_evals(&data_dir, &[linux_eval, darwin_eval]);
+        burndown::update(&data_dir, &jobset_evals[0], &jobset_evals[1], &platforms)?;
+        let mut out_dir = std::env::current_dir()?;
+        out_dir.push("public");
+        out_dir.push("data");
+        burndown::write(&data_dir, &out_dir, &generated_at)?;
+
+        let inputs = index::Inputs {
+            target_branch,
+            triggered_by: std::env::var("CI_PIPELINE_SOURCE").unwrap_or_else(|_| "???".to_string()),
+            linux_eval,
+            darwin_eval,
+            platform_pages,
+        };
+        let mut out = std::env::current_dir()?;
+        out.push("public");
+        out.push("index.html");
+        index::render(
+            &renderer,
+            &out,
+            &data_dir,
+            &inputs,
+            &platforms,
+            &details.categories,
+            now,
+        )?;
+    } else {
+        log::info!("Not rendering the landing page without --linux-eval and --darwin-eval");
+    }
+
+    Ok(())
+}
+
+/// Links the log excerpts of the tables to the pages of the maintainers instead of including
+/// them, so the page of all failed builds doesn't grow by every log
+fn link_logs(mut sections: Vec<Section>, handles: &Handles) -> Result<Vec<Section>> {
+    for section in &mut sections {
+        section.inline_logs = false;
+        for row in &mut section.rows {
+            let slug = handle(handles, &row.maintainer)?.slug();
+            row.log_url = format!("by-maintainer/{slug}.html#build-{}", row.build_id);
+            row.log = String::new();
         }
-        found = true;
-        out.write_fmt(format_args!("<tr><td><a href=\"https://hydra.nixos.org/build/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", build.build_id, build.attr, build.name, build.arch, build.maintainer, build.status))?;
     }
-    if !found {
-        out.write_fmt(format_args!(
-            r#"<tr><td colspan="5" class="none">None 🎉</td></tr>"#
-        ))?;
+    Ok(sections)
+}
+
+/// Ranks the direct failures of a maintainer by the number of builds they block
+fn blocking(builds: &[Build], root_causes: &RootCauses) -> Vec<BlockingEntry> {
+    let mut entries: Vec<_> = builds
+        .iter()
+        .filter(|build| build.status != "Dependency failed")
+        .filter_map(|build| {
+            let blocked: Vec<_> = root_causes
+                .blocked_by(build.build_id)
+                .map(|build_id| BlockedCell {
+                    attr: root_causes
+                        .attr_of(build_id)
+                        .map_or_else(|| format!("build {build_id}"), str::to_string),
+                    url: format!("https://hydra.nixos.org/build/{build_id}"),
+                })
+                .collect();
+            if blocked.is_empty() {
+                return None;
+            }
+            Some(BlockingEntry {
+                attr: build.attr.clone(),
+                build_id: build.build_id,
+                arch: build.arch.clone(),
+                count: blocked.len(),
+                blocked,
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then(a.attr.cmp(&b.attr)));
+    for entry in &mut entries {
+        entry.blocked.sort_by(|a, b| a.attr.cmp(&b.attr));
     }
-    // Write middle
-    out.write_fmt(format_args!(r#"</tbody>
-    </table>
-    <p>Jump to: <a href='#direct'>Direct Failures</a>&nbsp;&bull;&nbsp;<a href='#indirect'>Indirect Failures</a></p>
-    <h2 id="indirect">Indirect failures</h2>
-    <p>These are packages where a dependency failed to build.<br></p>
-    <table>
-      <thead><tr><th>Attribute</th><th>Job name</th><th>Platform</th><th>Result</th></th></thead>
-      <tbody>"#))?;
-    // Indirect failures
-    let mut found = false;
-    for attr in &all_attrs {
-        let build = &all_failed_builds.get(*attr).unwrap();
-        if build.status != "Dependency failed" {
+    entries
+}
+
+/// Returns the handle of a group that passed validation
+fn handle<'a>(handles: &'a Handles, name: &str) -> Result<&'a MaintainerHandle> {
+    handles
+        .get(name)
+        .ok_or_else(|| anyhow!("No handle for {name:?}"))
+}
+
+/// Reads a cache of builds that are grouped by their first field, like the maintainers cache
+fn read_grouped(path: &Path, eval: u64, groups: &mut Groups) -> Result<()> {
+    let lines = read_to_string(path)?;
+    for line in lines.split('\n') {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(6, ' ').collect();
+        if parts.len() != 6 {
+            return Err(anyhow!("Invalid line in {}: {line}", path.display()));
+        }
+        // Group by maintainer
+        let maintainer = parts[0].to_string();
+        let build = Build {
+            attr: parts[1].to_string(),
+            build_id: parts[2].parse::<u64>()?,
+            name: parts[3].to_string(),
+            arch: parts[4].to_string(),
+            status: parts[5].to_string(),
+            maintainer: maintainer.to_string(),
+            eval,
+        };
+        groups.entry(maintainer).or_default().push(build);
+    }
+    Ok(())
+}
+
+/// Reads the positions of the failed builds of the evaluations, by build ID. Builds of evaluations
+/// without a known nixpkgs revision have no position, as it couldn't be linked.
+fn read_positions(data_dir: &Path, evals: &[u64]) -> Result<HashMap<u64, SourcePosition>> {
+    let revisions = read_eval_revisions(data_dir)?;
+    let mut positions = HashMap::new();
+    for eval in evals {
+        let Some(revision) = revisions.get(eval) else {
+            log::warn!("No nixpkgs revision of evaluation {eval} recorded");
+            continue;
+        };
+        let mut eval_positions = read_position_cache(data_dir, *eval)?;
+        if eval_positions.is_empty() {
             continue;
         }
-        found = true;
-        out.write_fmt(format_args!("<tr><td><a href=\"https://hydra.nixos.org/build/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", build.build_id, build.attr, build.name, build.arch, build.maintainer, build.status))?;
+        for build in read_eval_cache(data_dir, *eval)? {
+            if let Some(position) = eval_positions.remove(&build.attr) {
+                positions.insert(
+                    build.build_id,
+                    SourcePosition {
+                        url: format!(
+                            "https://github.com/NixOS/nixpkgs/blob/{revision}/{}#L{}",
+                            position.file, position.line
+                        ),
+                        file: position.file,
+                        line: position.line,
+                    },
+                );
+            }
+        }
     }
-    if !found {
-        out.write_fmt(format_args!(
-            r#"<tr><td colspan="5" class="none">None 🎉</td></tr>"#
-        ))?;
+    log::info!("Loaded {} positions", positions.len());
+    Ok(positions)
+}
+
+/// Sorts the builds of each group and drops successful builds and groups without failures
+fn retain_failed(groups: &mut Groups) {
+    for builds in groups.values_mut() {
+        builds.sort_by(|a, b| a.attr.cmp(&b.attr));
+        builds.retain(|x| x.status != "Succeeded");
     }
-    // Write bottom
-    out.write_fmt(format_args!("</tbody></table></body></html>"))?;
+    groups.retain(|_, x| !x.is_empty());
+}
 
-    Ok(())
+/// Splits builds into the tables of direct and indirect failures
+fn sections<'a>(
+    builds: impl Iterator<Item = &'a Build>,
+    show_maintainer: bool,
+    details: &Details,
+) -> Vec<Section> {
+    let (indirect, direct): (Vec<_>, Vec<_>) = builds
+        .map(|build| {
+            let log = details.logs.get(&build.build_id);
+            let position = details.positions.get(&build.build_id);
+            Row {
+                build_id: build.build_id,
+                attr: build.attr.clone(),
+                name: build.name.clone(),
+                arch: build.arch.clone(),
+                maintainer: build.maintainer.clone(),
+                status: build.status.clone(),
+                reason: details
+                    .categories
+                    .get(&build.build_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                dependencies: details
+                    .root_causes
+                    .of(build.build_id)
+                    .map(|dependency| DependencyCell {
+                        name: dependency.name.clone(),
+                        arch: dependency.arch.clone(),
+                        url: format!("https://hydra.nixos.org/build/{}", dependency.build_id),
+                    })
+                    .collect(),
+                has_position: position.is_some(),
+                position: position.map_or_else(String::new, |position| {
+                    format!("{}:{}", position.file, position.line)
+                }),
+                position_url: position.map_or_else(String::new, |position| position.url.clone()),
+                has_log: log.is_some(),
+                log: log.cloned().unwrap_or_default(),
+                log_lines: log.map_or(0, |log| log.lines().count()),
+                log_url: String::new(),
+            }
+        })
+        .partition(|row| row.status == "Dependency failed");
+    vec![
+        Section::direct(show_maintainer, direct),
+        Section::indirect(show_maintainer, indirect),
+    ]
 }
@@ -0,0 +1,96 @@
+//! Pages of all failed builds of a single platform, in `public/failed/by-platform/`.
+//!
+//! The builds are taken from the evaluation caches rather than from the maintainers cache, so the
+//! pages contain the same builds that are counted on the landing page and in the metrics.
+
+use crate::render::{Meta, PlatformPage, Renderer};
+use crate::{sections, Build, Details, Groups};
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::create_dir_all;
+use std::path::Path;
+use zhf_common::cache::read_failed_builds;
+
+/// Failed builds of each platform
+pub type Platforms = BTreeMap<String, Vec<Build>>;
+
+/// Collects the failed builds of all platforms, with the maintainers of each build
+pub fn collect(data_dir: &Path, evals: &[u64], maintainers: &Groups) -> Result<Platforms> {
+    // All maintainers of each build. The maintainers cache prefixes the attributes of the nixpkgs
+    // jobset with `nixpkgs.`, so builds are matched by their ID.
+    let mut build_maintainers: HashMap<u64, BTreeSet<&str>> = HashMap::new();
+    for (maintainer, builds) in maintainers {
+        for build in builds {
+            build_maintainers
+                .entry(build.build_id)
+                .or_default()
+                .insert(maintainer);
+        }
+    }
+
+    let mut platforms = Platforms::new();
+    for (eval, build) in read_failed_builds(data_dir, evals)? {
+        let maintainer = build_maintainers
+            .get(&build.build_id)
+            .map(|names| names.iter().copied().collect::<Vec<_>>().join(", "))
+            .unwrap_or_default();
+        platforms
+            .entry(build.arch.clone())
+            .or_default()
+            .push(Build {
+                attr: build.attr,
+                build_id: build.build_id,
+                name: build.name,
+                arch: build.arch,
+                status: build.status,
+                maintainer,
+                eval,
+            });
+    }
+    Ok(platforms)
+}
+
+/// Renders a page for every platform with failed builds and returns the platforms that have one
+pub fn render(
+    renderer: &Renderer,
+    failed_dir: &Path,
+    platforms: &Platforms,
+    details: &Details,
+) -> Result<BTreeSet<String>> {
+    let out_dir = failed_dir.join("by-platform");
+    create_dir_all(&out_dir)?;
+    let mut rendered = BTreeSet::new();
+
+    for (system, builds) in platforms {
+        // Systems end up in file names
+        if !system
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            log::warn!("Skipping page of invalid system {system:?}");
+            continue;
+        }
+        let indirect = builds
+            .iter()
+            .filter(|build| build.status == "Dependency failed")
+            .count();
+        let page = PlatformPage {
+            meta: Meta {
+                title: format!("Hydra failures ({system})"),
+                heading: format!("Hydra failures on {system}"),
+                og_title: "Per-platform Hydra failures".to_string(),
+                description: format!("Track Hydra failures on {system}"),
+                path: format!("failed/by-platform/{system}.html"),
+                root: "../../",
+            },
+            total: builds.len(),
+            direct: builds.len() - indirect,
+            indirect,
+            sections: sections(builds.iter(), true, details),
+        };
+        renderer.render(&page, &out_dir.join(format!("{system}.html")))?;
+        rendered.insert(system.clone());
+    }
+    log::info!("Rendered {} platform pages", rendered.len());
+    Ok(rendered)
+}
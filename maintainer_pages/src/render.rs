@@ -0,0 +1,580 @@
+//! Page models and the template renderer.
+//!
+//! The templates in `templates/` are compiled into the binary and checked against the page models
+//! at build time. For experimenting with the layout, a directory of templates can be passed with
+//! `--templates`. Templates found in there are rendered at runtime instead of the built-in ones,
+//! every template that is missing falls back to the built-in version. Both engines escape HTML
+//! automatically, so only the templates themselves decide what is markup.
+
+use crate::sources::SourceFailure;
+use anyhow::{anyhow, Result};
+use askama::Template;
+use serde::Serialize;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// Sources of all built-in templates, used when a template is missing from the override directory
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("layout.html", include_str!("../templates/layout.html")),
+    (
+        "failures_table.html",
+        include_str!("../templates/failures_table.html"),
+    ),
+    (
+        "search_form.html",
+        include_str!("../templates/search_form.html"),
+    ),
+    (
+        "maintainer.html",
+        include_str!("../templates/maintainer.html"),
+    ),
+    ("overview.html", include_str!("../templates/overview.html")),
+    ("all.html", include_str!("../templates/all.html")),
+    ("sources.html", include_str!("../templates/sources.html")),
+    ("team.html", include_str!("../templates/team.html")),
+    ("teams.html", include_str!("../templates/teams.html")),
+    ("feed.xml", include_str!("../templates/feed.xml")),
+    ("rejected.html", include_str!("../templates/rejected.html")),
+    ("platform.html", include_str!("../templates/platform.html")),
+    ("index.html", include_str!("../templates/index.html")),
+    (
+        "eval_diff.html",
+        include_str!("../templates/eval_diff.html"),
+    ),
+];
+
+/// Renders pages either with the built-in templates or with templates loaded at runtime
+pub enum Renderer {
+    Builtin,
+    Custom(Box<minijinja::Environment<'static>>),
+}
+
+impl Renderer {
+    /// Creates a renderer that prefers the templates in `dir`, if given
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        let Some(dir) = dir else {
+            return Self::Builtin;
+        };
+        let mut env = minijinja::Environment::new();
+        env.set_loader(move |name| {
+            // Don't allow escaping the template directory
+            if name.split('/').any(|part| part == ".." || part.is_empty()) {
+                return Ok(None);
+            }
+            match read_to_string(dir.join(name)) {
+                Ok(source) => Ok(Some(source)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BUILTIN_TEMPLATES
+                    .iter()
+                    .find(|(builtin, _)| *builtin == name)
+                    .map(|(_, source)| (*source).to_string())),
+                Err(e) => Err(minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("Unable to read template {name}"),
+                )
+                .with_source(e)),
+            }
+        });
+        Self::Custom(Box::new(env))
+    }
+
+    /// Renders a page to a file
+    pub fn render<P: Page>(&self, page: &P, out: &Path) -> Result<()> {
+        let html = match self {
+            Self::Builtin => page.render()?,
+            Self::Custom(env) => env
+                .get_template(P::TEMPLATE)
+                .and_then(|template| template.render(page))
+                .map_err(|e| anyhow!("Unable to render {}: {e:#}", P::TEMPLATE))?,
+        };
+        std::fs::write(out, html)?;
+        Ok(())
+    }
+}
+
+/// A page that can be rendered by both template engines
+pub trait Page: Template + Serialize {
+    /// Name of the template in the templates directory
+    const TEMPLATE: &'static str;
+}
+
+/// Information in the head of every page
+#[derive(Serialize)]
+pub struct Meta {
+    pub title: String,
+    pub heading: String,
+    pub og_title: String,
+    pub description: String,
+    /// Path of the page relative to the site root
+    pub path: String,
+    /// Relative link to the site root, ending in a slash
+    pub root: &'static str,
+}
+
+/// A table of failed builds
+#[derive(Serialize)]
+pub struct Section {
+    /// HTML id of the heading, used by the "Jump to" links
+    pub id: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub show_maintainer: bool,
+    /// Whether to show the failure reason and log excerpt
+    pub show_details: bool,
+    /// Whether to show the failed dependencies
+    pub show_dependencies: bool,
+    /// Whether the log excerpts are part of the page, otherwise they are linked
+    pub inline_logs: bool,
+    /// Number of columns in the table
+    pub columns: usize,
+    pub rows: Vec<Row>,
+}
+
+impl Section {
+    /// Creates the table of direct failures
+    pub fn direct(show_maintainer: bool, rows: Vec<Row>) -> Self {
+        Self {
+            id: "direct",
+            title: "Direct failures",
+            description: "These are packages fail to build themselves.",
+            show_maintainer,
+            show_details: true,
+            show_dependencies: false,
+            inline_logs: true,
+            columns: if show_maintainer { 7 } else { 6 },
+            rows,
+        }
+    }
+
+    /// Creates the table of indirect failures
+    pub fn indirect(show_maintainer: bool, rows: Vec<Row>) -> Self {
+        Self {
+            id: "indirect",
+            title: "Indirect failures",
+            description: "These are packages where a dependency failed to build.",
+            show_maintainer,
+            show_details: false,
+            show_dependencies: true,
+            inline_logs: true,
+            columns: if show_maintainer { 6 } else { 5 },
+            rows,
+        }
+    }
+}
+
+/// A failed build
+#[derive(Serialize)]
+pub struct Row {
+    pub build_id: u64,
+    pub attr: String,
+    pub name: String,
+    pub arch: String,
+    pub maintainer: String,
+    pub status: String,
+    /// Failure category
+    pub reason: String,
+    /// Whether the file the build is defined in is known
+    pub has_position: bool,
+    /// File and line of `meta.position`
+    pub position: String,
+    /// Link to the position at the evaluated nixpkgs revision
+    pub position_url: String,
+    pub has_log: bool,
+    /// Tail of the build log
+    pub log: String,
+    pub log_lines: usize,
+    /// Link to the log excerpt on another page, if it's not part of the page
+    pub log_url: String,
+    /// Failed dependencies of indirect failures
+    pub dependencies: Vec<DependencyCell>,
+}
+
+/// A failed dependency of a build
+#[derive(Serialize)]
+pub struct DependencyCell {
+    pub name: String,
+    pub arch: String,
+    /// Link to the build the dependency failed in
+    pub url: String,
+}
+
+/// Failures of the packages of a single maintainer
+#[derive(Template, Serialize)]
+#[template(path = "maintainer.html")]
+pub struct MaintainerPage {
+    pub meta: Meta,
+    /// Handle of the maintainer, `_` for builds without maintainer
+    pub name: String,
+    /// File name of the feed of the maintainer
+    pub feed: String,
+    /// Direct failures that block other builds, most blocking first
+    pub blocking: Vec<BlockingEntry>,
+    pub sections: Vec<Section>,
+}
+
+impl Page for MaintainerPage {
+    const TEMPLATE: &'static str = "maintainer.html";
+}
+
+/// A direct failure that blocks other builds
+#[derive(Serialize)]
+pub struct BlockingEntry {
+    pub attr: String,
+    pub build_id: u64,
+    pub arch: String,
+    /// Number of blocked builds
+    pub count: usize,
+    pub blocked: Vec<BlockedCell>,
+}
+
+/// A build that is blocked by a direct failure
+#[derive(Serialize)]
+pub struct BlockedCell {
+    pub attr: String,
+    pub url: String,
+}
+
+/// A maintainer in the overview
+#[derive(Serialize)]
+pub struct OverviewEntry {
+    pub name: String,
+    /// File name of the page without extension
+    pub slug: String,
+    pub failed: usize,
+    /// Number of distinct builds blocked by the failures
+    pub blocking: usize,
+}
+
+/// List of all maintainers with failed packages
+#[derive(Template, Serialize)]
+#[template(path = "overview.html")]
+pub struct OverviewPage {
+    pub meta: Meta,
+    pub maintainers: Vec<OverviewEntry>,
+    /// Whether the maintainers are sorted by the number of blocked builds instead of by name
+    pub by_blocked: bool,
+    /// Number of rejected handles
+    pub rejected: usize,
+}
+
+impl Page for OverviewPage {
+    const TEMPLATE: &'static str = "overview.html";
+}
+
+/// Failures of the packages of a team
+#[derive(Template, Serialize)]
+#[template(path = "team.html")]
+pub struct TeamPage {
+    pub meta: Meta,
+    /// GitHub handles of the members
+    pub members: Vec<String>,
+    pub sections: Vec<Section>,
+}
+
+impl Page for TeamPage {
+    const TEMPLATE: &'static str = "team.html";
+}
+
+/// A team in the overview
+#[derive(Serialize)]
+pub struct TeamEntry {
+    /// Name of the team in `lib.teams`
+    pub name: String,
+    /// File name of the page without extension
+    pub slug: String,
+    pub short_name: String,
+    pub failed: usize,
+}
+
+/// List of all teams with failed packages
+#[derive(Template, Serialize)]
+#[template(path = "teams.html")]
+pub struct TeamsPage {
+    pub meta: Meta,
+    pub teams: Vec<TeamEntry>,
+}
+
+impl Page for TeamsPage {
+    const TEMPLATE: &'static str = "teams.html";
+}
+
+/// All failed builds
+#[derive(Template, Serialize)]
+#[template(path = "all.html")]
+pub struct AllPage {
+    pub meta: Meta,
+    pub sections: Vec<Section>,
+}
+
+impl Page for AllPage {
+    const TEMPLATE: &'static str = "all.html";
+}
+
+/// All failed builds of a platform
+#[derive(Template, Serialize)]
+#[template(path = "platform.html")]
+pub struct PlatformPage {
+    pub meta: Meta,
+    pub total: usize,
+    pub direct: usize,
+    pub indirect: usize,
+    pub sections: Vec<Section>,
+}
+
+impl Page for PlatformPage {
+    const TEMPLATE: &'static str = "platform.html";
+}
+
+/// A failed source download
+#[derive(Serialize)]
+pub struct SourceRow {
+    pub name: String,
+    pub arch: String,
+    pub problem: &'static str,
+    pub log_url: String,
+    pub specified: String,
+    pub got: String,
+    pub has_url: bool,
+    pub url: String,
+    pub blocked: usize,
+}
+
+impl From<&SourceFailure> for SourceRow {
+    fn from(failure: &SourceFailure) -> Self {
+        Self {
+            name: failure.name.clone(),
+            arch: failure.arch.clone(),
+            problem: failure.problem,
+            log_url: failure.log_url(),
+            specified: failure.specified.clone().unwrap_or_default(),
+            got: failure.got.clone().unwrap_or_default(),
+            has_url: failure.url.is_some(),
+            url: failure.url.clone().unwrap_or_default(),
+            blocked: failure.blocked,
+        }
+    }
+}
+
+/// All failed source downloads
+#[derive(Template, Serialize)]
+#[template(path = "sources.html")]
+pub struct SourcesPage {
+    pub meta: Meta,
+    pub failures: Vec<SourceRow>,
+}
+
+impl Page for SourcesPage {
+    const TEMPLATE: &'static str = "sources.html";
+}
+
+/// An entry of an Atom feed
+#[derive(Serialize)]
+pub struct FeedItem {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub updated: String,
+    pub summary: String,
+}
+
+/// Atom feed of builds that newly fail or got fixed
+#[derive(Template, Serialize)]
+#[template(path = "feed.xml")]
+pub struct FeedPage {
+    /// URL of the feed, which is also its ID
+    pub id: String,
+    pub title: String,
+    pub page_url: String,
+    pub updated: String,
+    pub entries: Vec<FeedItem>,
+}
+
+impl Page for FeedPage {
+    const TEMPLATE: &'static str = "feed.xml";
+}
+
+/// A handle that was rejected
+#[derive(Serialize)]
+pub struct RejectedEntry {
+    pub name: String,
+    pub reason: &'static str,
+    pub builds: usize,
+}
+
+/// Report of rejected maintainer and team handles
+#[derive(Template, Serialize)]
+#[template(path = "rejected.html")]
+pub struct RejectedPage {
+    pub meta: Meta,
+    pub rejected: Vec<RejectedEntry>,
+}
+
+impl Page for RejectedPage {
+    const TEMPLATE: &'static str = "rejected.html";
+}
+
+/// An evaluation on the landing page
+#[derive(Serialize)]
+pub struct EvalCell {
+    pub id: u64,
+    pub time: String,
+}
+
+/// Number of failed builds of a platform
+#[derive(Serialize)]
+pub struct SystemCount {
+    pub system: String,
+    pub count: usize,
+    /// Whether the platform page was rendered
+    pub has_page: bool,
+}
+
+/// Number of direct failures of a failure category
+#[derive(Serialize)]
+pub struct CategoryCount {
+    pub name: String,
+    pub count: usize,
+}
+
+/// A failed dependency that blocks many builds
+#[derive(Serialize)]
+pub struct ProblematicDep {
+    pub name: String,
+    /// Build of the first platform the dependency failed on
+    pub url: String,
+    pub dependants: usize,
+    pub platforms: Vec<ProblematicPlatform>,
+    pub blocked: Vec<ProblematicBlocked>,
+}
+
+/// A platform a problematic dependency failed on
+#[derive(Serialize)]
+pub struct ProblematicPlatform {
+    pub system: String,
+    pub url: String,
+    pub dependants: usize,
+}
+
+/// A build that is blocked by a problematic dependency
+#[derive(Serialize)]
+pub struct ProblematicBlocked {
+    pub attr: String,
+    pub url: String,
+    /// Other problematic dependencies that block the build, separated by commas
+    pub also_blocked_by: String,
+}
+
+/// An evaluation of a jobset around a merge of staging-next
+#[derive(Serialize)]
+pub struct ImpactEval {
+    pub jobset: String,
+    pub has_before: bool,
+    pub before: u64,
+    pub has_after: bool,
+    pub after: u64,
+}
+
+/// Failed builds of a system before and after a merge of staging-next
+#[derive(Serialize)]
+pub struct ImpactSystem {
+    pub system: String,
+    pub before: usize,
+    pub after: usize,
+    /// Difference with sign, like `+3`
+    pub delta: String,
+}
+
+/// An attribute that fails since a merge of staging-next
+#[derive(Serialize)]
+pub struct ImpactBroken {
+    pub attr: String,
+    pub url: String,
+}
+
+/// What a merge of staging-next changed
+#[derive(Serialize)]
+pub struct StagingImpact {
+    pub commit: String,
+    pub short_commit: String,
+    pub url: String,
+    pub time: String,
+    pub evals: Vec<ImpactEval>,
+    pub systems: Vec<ImpactSystem>,
+    pub broken_count: usize,
+    pub newly_broken: Vec<ImpactBroken>,
+}
+
+/// The landing page
+#[derive(Template, Serialize)]
+#[template(path = "index.html")]
+pub struct IndexPage {
+    pub target_branch: String,
+    pub last_check: String,
+    pub triggered_by: String,
+    pub linux_eval: EvalCell,
+    pub darwin_eval: EvalCell,
+    pub systems: Vec<SystemCount>,
+    pub total: usize,
+    pub categories: Vec<CategoryCount>,
+    pub problematic: Vec<ProblematicDep>,
+    /// Most recent merge first
+    pub staging_impacts: Vec<StagingImpact>,
+    /// Data of the charts as JSON that is safe to embed in a script element
+    pub chart_data: String,
+}
+
+impl Page for IndexPage {
+    const TEMPLATE: &'static str = "index.html";
+}
+
+/// A commit on the eval diff page
+#[derive(Clone, Serialize)]
+pub struct DiffCommit {
+    pub commit: String,
+    pub short_commit: String,
+    pub url: String,
+    pub summary: String,
+    pub author: String,
+    pub time: String,
+}
+
+/// An attribute that newly fails, with the commits that touch its file
+#[derive(Serialize)]
+pub struct DiffBroken {
+    pub attr: String,
+    pub system: String,
+    pub url: String,
+    pub has_position: bool,
+    /// File and line of `meta.position`
+    pub position: String,
+    pub suspects: Vec<DiffCommit>,
+}
+
+/// The changes between the two latest evaluations of a jobset
+#[derive(Serialize)]
+pub struct DiffJobset {
+    pub jobset: String,
+    /// Whether there is a previous evaluation to compare to
+    pub has_diff: bool,
+    pub old_eval: u64,
+    pub new_eval: u64,
+    pub old_revision: String,
+    pub new_revision: String,
+    pub compare_url: String,
+    pub commit_count: usize,
+    pub broken_count: usize,
+    pub newly_broken: Vec<DiffBroken>,
+    /// All commits between the evaluations, newest first
+    pub commits: Vec<DiffCommit>,
+}
+
+/// Newly failing attributes and the commits that could have broken them
+#[derive(Template, Serialize)]
+#[template(path = "eval_diff.html")]
+pub struct EvalDiffPage {
+    pub meta: Meta,
+    pub jobsets: Vec<DiffJobset>,
+}
+
+impl Page for EvalDiffPage {
+    const TEMPLATE: &'static str = "eval_diff.html";
+}
@@ -0,0 +1,102 @@
+//! Compact index of all failed builds for the client-side search in `js/search.js`.
+//!
+//! To keep the index small, systems, statuses, categories and maintainers are stored once in
+//! lists, and builds refer to them by their position. Each build is a list of the attribute, job
+//! name, build ID, index of the system, index of the status, index of the category (-1 if
+//! there is none) and a list of the indices of the maintainers.
+
+use crate::{Build, Groups};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct SearchIndex<'a> {
+    systems: Vec<&'a str>,
+    statuses: Vec<&'a str>,
+    categories: Vec<&'a str>,
+    maintainers: Vec<&'a str>,
+    builds: Vec<IndexedBuild<'a>>,
+}
+
+/// A build in the index, serialized as a list
+#[derive(Serialize)]
+struct IndexedBuild<'a>(&'a str, &'a str, u64, usize, usize, isize, Vec<usize>);
+
+/// Writes the search index of all failed builds
+pub fn write(
+    out: &Path,
+    maintainers: &Groups,
+    all_builds: &[&Build],
+    categories: &HashMap<u64, String>,
+) -> Result<()> {
+    let mut build_maintainers: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (maintainer, builds) in maintainers {
+        for build in builds {
+            build_maintainers
+                .entry(&build.attr)
+                .or_default()
+                .insert(maintainer);
+        }
+    }
+
+    let mut systems = Interner::default();
+    let mut statuses = Interner::default();
+    let mut category_names = Interner::default();
+    let mut maintainer_names = Interner::default();
+    let mut builds = vec![];
+    for build in all_builds {
+        builds.push(IndexedBuild(
+            build.attr.as_str(),
+            build.name.as_str(),
+            build.build_id,
+            systems.intern(&build.arch),
+            statuses.intern(&build.status),
+            categories
+                .get(&build.build_id)
+                .map_or(-1, |category| category_names.intern(category) as isize),
+            build_maintainers
+                .get(build.attr.as_str())
+                .map(|names| {
+                    names
+                        .iter()
+                        .map(|name| maintainer_names.intern(name))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ));
+    }
+
+    let index = SearchIndex {
+        systems: systems.into_list(),
+        statuses: statuses.into_list(),
+        categories: category_names.into_list(),
+        maintainers: maintainer_names.into_list(),
+        builds,
+    };
+    serde_json::to_writer(File::create(out)?, &index)?;
+    Ok(())
+}
+
+/// Assigns each distinct string its position in a list
+#[derive(Default)]
+struct Interner<'a> {
+    indices: BTreeMap<&'a str, usize>,
+}
+
+impl<'a> Interner<'a> {
+    fn intern(&mut self, s: &'a str) -> usize {
+        let next = self.indices.len();
+        *self.indices.entry(s).or_insert(next)
+    }
+
+    fn into_list(self) -> Vec<&'a str> {
+        let mut list = vec![""; self.indices.len()];
+        for (s, i) in self.indices {
+            list[i] = s;
+        }
+        list
+    }
+}
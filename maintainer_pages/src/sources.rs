@@ -0,0 +1,188 @@
+//! Report of failed source fetches. These are fixed-output derivations that fail because of hash
+//! mismatches or dead upstream URLs, which anyone can fix without knowing the package.
+
+use crate::render::{Meta, Renderer, SourceRow, SourcesPage};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::Path;
+use zhf_common::cache::{
+    cache_file, log_file, read_dep_cache, read_eval_cache, read_most_important_cache, step_log_file,
+};
+use zhf_common::store_path::looks_like_source;
+
+/// Failure categories of direct failures that indicate a failed source fetch
+const SOURCE_CATEGORIES: &[&str] = &["hash-mismatch", "download-failure"];
+
+/// A source that couldn't be fetched
+pub struct SourceFailure {
+    pub name: String,
+    pub arch: String,
+    /// Build the fetch failed in
+    pub build_id: u64,
+    /// Step of the build the fetch failed in, if it's not the build itself
+    pub step_nr: Option<u64>,
+    pub problem: &'static str,
+    /// Hash the derivation specifies
+    pub specified: Option<String>,
+    /// Hash of what was actually downloaded
+    pub got: Option<String>,
+    pub url: Option<String>,
+    /// Number of builds that fail because of this
+    pub blocked: usize,
+}
+
+/// Finds all failed source fetches of some evaluations
+pub fn collect(
+    data_dir: &Path,
+    evals: &[u64],
+    categories: &HashMap<u64, String>,
+) -> Result<Vec<SourceFailure>> {
+    let mut failures = vec![];
+    let mut seen = HashSet::new();
+    for eval in evals {
+        // Sources that fail as a dependency of other builds
+        if cache_file(data_dir, "mostimportantcache", *eval).exists() {
+            let mut blocked: HashMap<u64, HashSet<u64>> = HashMap::new();
+            for entry in read_dep_cache(data_dir, *eval)? {
+                blocked
+                    .entry(entry.root_build_id)
+                    .or_default()
+                    .insert(entry.build_id);
+            }
+            for root in read_most_important_cache(data_dir, *eval)? {
+                if !looks_like_source(&root.name)
+                    || !seen.insert((root.build_id, root.name.clone()))
+                {
+                    continue;
+                }
+                let log = root
+                    .step_nr
+                    .and_then(|nr| read_to_string(step_log_file(data_dir, root.build_id, nr)).ok())
+                    .unwrap_or_default();
+                failures.push(SourceFailure::from_log(
+                    root.name,
+                    root.arch,
+                    root.build_id,
+                    root.step_nr,
+                    &log,
+                    blocked.get(&root.build_id).map_or(0, HashSet::len),
+                ));
+            }
+        }
+        // Jobs that are sources themselves
+        for build in read_eval_cache(data_dir, *eval)? {
+            let is_source = categories
+                .get(&build.build_id)
+                .is_some_and(|category| SOURCE_CATEGORIES.contains(&category.as_str()));
+            if !is_source || !seen.insert((build.build_id, build.name.clone())) {
+                continue;
+            }
+            let log = read_to_string(log_file(data_dir, build.build_id)).unwrap_or_default();
+            failures.push(SourceFailure::from_log(
+                build.name,
+                build.arch,
+                build.build_id,
+                None,
+                &log,
+                0,
+            ));
+        }
+    }
+    failures.sort_by(|a, b| b.blocked.cmp(&a.blocked).then(a.name.cmp(&b.name)));
+    Ok(failures)
+}
+
+impl SourceFailure {
+    /// Extracts the hashes and URL from the log of the failed fetch
+    fn from_log(
+        name: String,
+        arch: String,
+        build_id: u64,
+        step_nr: Option<u64>,
+        log: &str,
+        blocked: usize,
+    ) -> Self {
+        let mut specified = None;
+        let mut got = None;
+        let mut url = None;
+        for line in log.lines() {
+            let line = line.trim();
+            if let Some(hash) = line
+                .strip_prefix("specified:")
+                .or_else(|| line.strip_prefix("wanted:"))
+            {
+                specified = Some(hash.trim().to_string());
+            } else if let Some(hash) = line.strip_prefix("got:") {
+                got = Some(hash.trim().to_string());
+            }
+            // fetchurl announces every URL it tries, so the last one is the one that failed
+            if let Some(tried) = line.strip_prefix("trying ") {
+                url = Some(trim_url(tried));
+            } else if url.is_none() {
+                url = line
+                    .split_whitespace()
+                    .find(|word| word.starts_with("https://") || word.starts_with("http://"))
+                    .map(trim_url);
+            }
+        }
+        let problem = if specified.is_some() || got.is_some() {
+            "Hash mismatch"
+        } else if log.contains("cannot download")
+            || log.contains("curl: (")
+            || log.contains("unable to download")
+        {
+            "Download failed"
+        } else {
+            "Unknown"
+        };
+        Self {
+            name,
+            arch,
+            build_id,
+            step_nr,
+            problem,
+            specified,
+            got,
+            url,
+            blocked,
+        }
+    }
+
+    /// Link to the log of the failed fetch on Hydra
+    pub fn log_url(&self) -> String {
+        match self.step_nr {
+            Some(nr) => format!(
+                "https://hydra.nixos.org/build/{}/nixlog/{nr}",
+                self.build_id
+            ),
+            None => format!("https://hydra.nixos.org/build/{}/log", self.build_id),
+        }
+    }
+}
+
+/// Removes quotes and punctuation around URLs found in logs
+fn trim_url(url: &str) -> String {
+    url.trim_matches(|c: char| "'\"`,;:()<>".contains(c))
+        .to_string()
+}
+
+/// Renders `sources.html`
+pub fn render(renderer: &Renderer, out: &Path, failures: &[SourceFailure]) -> Result<()> {
+    renderer.render(
+        &SourcesPage {
+            meta: Meta {
+                title: "Failed source downloads".to_string(),
+                heading: "Failed source downloads".to_string(),
+                og_title: "Failed source downloads".to_string(),
+                description:
+                    "Sources that fail to download on Hydra because of hash mismatches or dead URLs"
+                        .to_string(),
+                path: "failed/sources.html".to_string(),
+                root: "../",
+            },
+            failures: failures.iter().map(SourceRow::from).collect(),
+        },
+        out,
+    )
+}
@@ -0,0 +1,129 @@
+//! Pages of nixpkgs teams (`meta.teams`). Many packages are maintained by a team rather than
+//! by individuals, so failures are also aggregated by team.
+//!
+//! The teams cache has the same format as the maintainers cache, with the name of the team in
+//! `lib.teams` instead of a GitHub handle. Next to it, `{eval}.members` lists the teams with their
+//! comma-separated members (`-` if there are none) and their short name.
+
+use crate::handles::{reject_invalid, Handles, MaintainerHandle, Rejected};
+use crate::render::{Meta, Renderer, TeamEntry, TeamPage, TeamsPage};
+use crate::{read_grouped, retain_failed, sections, Details, Groups};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_to_string};
+use std::path::Path;
+
+/// A team from `lib.teams`
+pub struct Team {
+    pub short_name: String,
+    /// GitHub handles of the members
+    pub members: Vec<String>,
+}
+
+/// Reads the teams and the builds of each team
+pub fn read(
+    data_dir: &Path,
+    evals: &[u64],
+    rejected: &mut Vec<Rejected>,
+) -> Result<(HashMap<String, Team>, Groups)> {
+    let mut teams = HashMap::new();
+    let mut builds = Groups::new();
+    let mut cache_dir = data_dir.to_path_buf();
+    cache_dir.push("teamscache");
+    for eval in evals {
+        let cache_loc = cache_dir.join(format!("{eval}.cache"));
+        // Caches from before teams were fetched
+        if !cache_loc.exists() {
+            log::warn!("No teams cache for evaluation {eval}");
+            continue;
+        }
+        read_grouped(&cache_loc, *eval, &mut builds)?;
+        let members_loc = cache_dir.join(format!("{eval}.members"));
+        for line in read_to_string(&members_loc)?.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.splitn(3, ' ').collect();
+            if parts.len() != 3 {
+                return Err(anyhow!("Invalid line in {}: {line}", members_loc.display()));
+            }
+            let members = match parts[1] {
+                "-" => vec![],
+                members => members.split(',').map(str::to_string).collect(),
+            };
+            teams.insert(
+                parts[0].to_string(),
+                Team {
+                    short_name: parts[2].to_string(),
+                    members,
+                },
+            );
+        }
+    }
+    // All builds of teams also have maintainers, so they don't need to be moved to nobody
+    rejected.extend(reject_invalid(&mut builds, false));
+    retain_failed(&mut builds);
+    Ok((teams, builds))
+}
+
+/// Renders `teams.html` and a page for every team
+pub fn render(
+    renderer: &Renderer,
+    failed_dir: &Path,
+    teams: &HashMap<String, Team>,
+    builds: &Groups,
+    details: &Details,
+) -> Result<()> {
+    let out_dir = failed_dir.join("by-team");
+    create_dir_all(&out_dir)?;
+
+    let handles = Handles::new(builds.keys().map(String::as_str));
+    let mut team_names: Vec<_> = builds.keys().collect();
+    team_names.sort();
+    let mut entries = vec![];
+    for team_name in team_names {
+        let team_builds = &builds[team_name];
+        let Some(slug) = handles.get(team_name).map(MaintainerHandle::slug) else {
+            continue;
+        };
+        let (short_name, members) = match teams.get(team_name) {
+            Some(team) => (team.short_name.clone(), team.members.clone()),
+            None => (team_name.clone(), vec![]),
+        };
+        let page = TeamPage {
+            meta: Meta {
+                title: format!("Hydra failures ({short_name})"),
+                heading: format!("Hydra failures for packages maintained by {short_name}"),
+                og_title: "Per-team Hydra failures".to_string(),
+                description: format!(
+                    "Track Hydra failures that have the {short_name} team as their maintainer"
+                ),
+                path: format!("failed/by-team/{slug}.html"),
+                root: "../../",
+            },
+            members,
+            sections: sections(team_builds.iter(), false, details),
+        };
+        renderer.render(&page, &out_dir.join(format!("{slug}.html")))?;
+        entries.push(TeamEntry {
+            name: team_name.clone(),
+            slug: slug.to_string(),
+            short_name,
+            failed: team_builds.len(),
+        });
+    }
+    log::info!("Rendered {} team pages", entries.len());
+
+    let page = TeamsPage {
+        meta: Meta {
+            title: "Hydra failures by team".to_string(),
+            heading: "Hydra failures by team".to_string(),
+            og_title: "Hydra failures by team".to_string(),
+            description: "Overview of teams maintaining broken Hydra packages".to_string(),
+            path: "failed/teams.html".to_string(),
+            root: "../",
+        },
+        teams: entries,
+    };
+    renderer.render(&page, &failed_dir.join("teams.html"))
+}
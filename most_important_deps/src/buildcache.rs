@@ -0,0 +1,176 @@
+//! Persistent cache of parsed Hydra build pages.
+//!
+//! Hydra reuses build IDs when a derivation doesn't change between evaluations, so the parsed
+//! pages are cached by build ID instead of by evaluation. This way they survive eval rotation and
+//! only builds that are new to an evaluation are fetched from Hydra.
+//!
+//! Only the pages of builds that failed directly are final. Builds whose failures were propagated
+//! from other builds, or that show no failed steps at all, change when the builds they depend on
+//! are restarted or fixed, so their entries expire after [`NON_FINAL_TTL`] and are fetched again.
+//!
+//! Each build is stored in its own file named `{build_id}.cache`. The first line contains the
+//! architecture and package name, separated by a space. Each following line is a failed step,
+//! consisting of the build ID the failure was propagated from, the number of the step in that
+//! build (`-` if unknown) and the store path(s) of the step, separated by spaces.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long entries of builds that didn't fail directly are used before fetching them again
+const NON_FINAL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A build step that failed
+#[derive(Clone)]
+pub struct FailedStep {
+    /// Store path(s) of the step, comma-separated if there are multiple outputs
+    pub store_path: String,
+    /// Hydra build the failure belongs to (the build itself if the step was not propagated)
+    pub build_id: u64,
+    /// Number of the step in `build_id`, if Hydra links its log
+    pub step_nr: Option<u64>,
+}
+
+/// Everything we need to know about a build page
+pub struct BuildInfo {
+    pub arch: String,
+    pub pkg_name: String,
+    pub failed_steps: Vec<FailedStep>,
+}
+
+impl BuildInfo {
+    /// Whether all failed steps failed in the build itself, so the page won't change anymore
+    fn is_final(&self, build_id: u64) -> bool {
+        !self.failed_steps.is_empty()
+            && self
+                .failed_steps
+                .iter()
+                .all(|step| step.build_id == build_id)
+    }
+}
+
+/// Build page cache living in a directory
+#[derive(Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    /// Opens the cache, creating the directory if needed
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path_of(&self, build_id: u64) -> PathBuf {
+        let mut path = self.dir.clone();
+        path.push(format!("{build_id}.cache"));
+        path
+    }
+
+    /// Returns the cached build page. Unreadable and expired entries are treated as missing.
+    pub async fn get(&self, build_id: u64) -> Option<BuildInfo> {
+        let path = self.path_of(build_id);
+        let contents = tokio::fs::read_to_string(&path).await.ok()?;
+        let info = match parse_entry(&contents) {
+            Ok(info) => info,
+            Err(e) => {
+                log::warn!("Ignoring invalid build cache entry of build #{build_id}: {e}");
+                return None;
+            }
+        };
+        if !info.is_final(build_id) {
+            let age = tokio::fs::metadata(&path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > NON_FINAL_TTL {
+                log::debug!("Build cache entry of build #{build_id} expired");
+                return None;
+            }
+        }
+        Some(info)
+    }
+
+    /// Stores a build page in the cache
+    pub async fn put(&self, build_id: u64, info: &BuildInfo) -> Result<()> {
+        let mut contents = format!("{} {}\n", info.arch, info.pkg_name);
+        for step in &info.failed_steps {
+            let step_nr = step.step_nr.map_or("-".to_string(), |nr| nr.to_string());
+            contents.push_str(&format!(
+                "{} {step_nr} {}\n",
+                step.build_id, step.store_path
+            ));
+        }
+        // Write to a temporary file first so aborted runs don't leave half-written entries
+        let path = self.path_of(build_id);
+        let mut new_path = path.clone();
+        new_path.set_extension("cache.new");
+        tokio::fs::write(&new_path, contents).await?;
+        tokio::fs::rename(new_path, path).await?;
+        Ok(())
+    }
+
+    /// Removes all entries of builds that are not in `referenced`
+    pub fn gc(&self, referenced: &HashSet<u64>) -> Result<()> {
+        let mut purged = 0;
+        for path in std::fs::read_dir(&self.dir)? {
+            let path = path?;
+            let file_name = path.file_name();
+            let file_name = file_name
+                .to_str()
+                .ok_or_else(|| anyhow!("Cache entry has no filename"))?;
+            // Leftovers of aborted runs
+            if file_name.ends_with(".cache.new") {
+                std::fs::remove_file(path.path())?;
+                continue;
+            }
+            let id = if let Some(Ok(id)) = file_name.strip_suffix(".cache").map(str::parse::<u64>) {
+                id
+            } else {
+                // Invalid entry
+                continue;
+            };
+            if !referenced.contains(&id) {
+                std::fs::remove_file(path.path())?;
+                purged += 1;
+            }
+        }
+        log::info!("Purged {purged} builds from the build cache");
+        Ok(())
+    }
+}
+
+fn parse_entry(contents: &str) -> Result<BuildInfo> {
+    let mut lines = contents.lines();
+    let (arch, pkg_name) = lines
+        .next()
+        .and_then(|line| line.split_once(' '))
+        .ok_or_else(|| anyhow!("No header found"))?;
+    let mut failed_steps = vec![];
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+        if parts.len() != 3 {
+            return Err(anyhow!("Invalid step line: {line}"));
+        }
+        failed_steps.push(FailedStep {
+            store_path: parts[2].to_owned(),
+            build_id: parts[0].parse::<u64>()?,
+            step_nr: match parts[1] {
+                "-" => None,
+                nr => Some(nr.parse::<u64>()?),
+            },
+        });
+    }
+    Ok(BuildInfo {
+        arch: arch.to_owned(),
+        pkg_name: pkg_name.to_owned(),
+        failed_steps,
+    })
+}
@@ -1,30 +1,57 @@
 //! Find the failed dependency storepath basenames of a build
 
+mod buildcache;
+mod pages;
+mod ranking;
+
 use anyhow::{anyhow, Result};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use select::node::Node;
-use select::predicate::{And, Attr, Class, Name, Predicate};
-use std::collections::HashMap;
+use buildcache::{BuildCache, FailedStep};
+use pages::BuildPages;
+use ranking::RankingOptions;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::{create_dir_all, read_to_string};
+use std::path::Path;
 use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 use wg::AsyncWaitGroup;
+use zhf_common::cache::read_eval_cache;
+use zhf_common::http::hydra_client;
+use zhf_common::store_path::StorePath;
 
 /// Number of parallel HTTP requests that are sent to Hydra
 const PARALLEL_REQUESTS: usize = 4;
+/// Maximum number of propagated failures that are followed to find the root cause of a failure
+const MAX_CHAIN_DEPTH: usize = 32;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::builder().format_timestamp(None).init();
     // Handle args
-    let argv: Vec<u64> = std::env::args()
-        .skip(1)
-        .map(|x| x.parse::<u64>().unwrap())
-        .collect();
+    let mut argv: Vec<u64> = Vec::new();
+    let mut ranking_options = RankingOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{arg} needs a value"));
+        match arg.as_str() {
+            "--top" => ranking_options.top = value()?.parse::<usize>()?,
+            "--platform-weight" => ranking_options.add_platform_weight(&value()?)?,
+            "--nixos-test-weight" => ranking_options.nixos_test_weight = value()?.parse::<f64>()?,
+            "--channel-blocking" => {
+                ranking_options.channel_blocking = read_to_string(value()?)?
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            }
+            "--channel-blocking-weight" => {
+                ranking_options.channel_blocking_weight = value()?.parse::<f64>()?;
+            }
+            _ => argv.push(arg.parse::<u64>()?),
+        }
+    }
     log::info!("Will crawl evaluations: {:?}", argv);
 
     // Prepare directories
@@ -36,11 +63,13 @@ async fn main() -> Result<()> {
     let mut depdir = data_dir.clone();
     depdir.push("depcache");
     create_dir_all(&depdir)?;
+    let mut build_cache_dir = data_dir.clone();
+    build_cache_dir.push("buildcache");
+    let build_cache = BuildCache::new(build_cache_dir)?;
 
     // Find all build IDs
     let mut evals = HashMap::new();
     for eval in &argv {
-        let mut build_ids = vec![];
         let mut cache_loc = most_important_dir.clone();
         cache_loc.push(format!("{eval}.cache"));
         let mut dep_cache_loc = depdir.clone();
@@ -50,21 +79,7 @@ async fn main() -> Result<()> {
             continue;
         }
 
-        let mut eval_loc = data_dir.clone();
-        eval_loc.push("evalcache");
-        eval_loc.push(format!("{eval}.cache"));
-        let lines = read_to_string(eval_loc)?;
-        let lines: Vec<&str> = lines.split('\n').collect();
-        for line in lines {
-            if line.is_empty() {
-                continue;
-            }
-            let parts: Vec<&str> = line.splitn(5, ' ').collect();
-            if parts[4] != "Dependency failed" {
-                continue;
-            }
-            build_ids.push(parts[1].parse::<u64>()?);
-        }
+        let build_ids = dependency_failed_builds_of(&data_dir, *eval)?;
         evals.insert(eval, build_ids);
     }
     let num_build_ids: usize = evals.values().map(Vec::len).sum();
@@ -72,15 +87,12 @@ async fn main() -> Result<()> {
 
     // Spawn tasks for getting the failed dependencies and writing them to files
     if num_build_ids > 0 {
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(10);
-        let http_client = ClientBuilder::new(
-            reqwest::Client::builder()
-                .user_agent("zh.fail scraper, please contact @dasJ on GitHub")
-                .build()?,
-        )
-        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build();
-        let http_semaphore = Arc::new(Semaphore::new(PARALLEL_REQUESTS));
+        let (http_client, rate_limiter) = hydra_client()?;
+        let build_pages = Arc::new(BuildPages::new(
+            build_cache.clone(),
+            http_client,
+            PARALLEL_REQUESTS,
+        ));
         let wg = AsyncWaitGroup::new();
         for (eval_id, build_ids) in &evals {
             let mut cache_loc = most_important_dir.clone();
@@ -91,14 +103,12 @@ async fn main() -> Result<()> {
             dep_cache_loc.push(format!("{eval_id}.cache.new"));
             let dep_cache_to_write = Arc::new(Mutex::new(File::create(&dep_cache_loc).await?));
             for build_id in build_ids {
-                let http_client = http_client.clone();
                 let t_wg = wg.add(1);
                 tokio::spawn(fetch_failed_deps_of_wrapped(
                     *build_id,
                     file_to_write.clone(),
                     dep_cache_to_write.clone(),
-                    http_client,
-                    http_semaphore.clone(),
+                    build_pages.clone(),
                     t_wg,
                 ));
             }
@@ -123,6 +133,8 @@ async fn main() -> Result<()> {
                 break;
             }
         }
+        rate_limiter.log_stats();
+        rate_limiter.write_stats(&data_dir, "most_important_deps")?;
 
         for eval_id in evals.keys() {
             // Move file to final destination
@@ -174,7 +186,7 @@ async fn main() -> Result<()> {
         }
     }
 
-    for path in std::fs::read_dir(depdir)? {
+    for path in std::fs::read_dir(&depdir)? {
         let path = path?;
         // Ignore none-cache entries
         if !path
@@ -205,26 +217,86 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Clean build cache. Keep all builds that are part of a failure chain of an evaluation we keep,
+    // including the root causes of depcaches from before the chains were recorded. If a depcache
+    // can't be read, the builds it references are unknown, so nothing is cleaned.
+    let mut referenced_builds = HashSet::new();
+    let mut all_referenced = true;
+    for eval in &argv {
+        referenced_builds.extend(dependency_failed_builds_of(&data_dir, *eval)?);
+        let mut dep_cache_loc = depdir.clone();
+        dep_cache_loc.push(format!("{eval}.cache"));
+        if !dep_cache_loc.exists() {
+            continue;
+        }
+        match dep_cache_builds(&read_to_string(dep_cache_loc)?) {
+            Ok(builds) => referenced_builds.extend(builds),
+            Err(e) => {
+                log::warn!("Not cleaning the build cache, depcache of {eval} is invalid: {e:#}");
+                all_referenced = false;
+            }
+        }
+    }
+    if all_referenced {
+        build_cache.gc(&referenced_builds)?;
+    }
+
+    // Rank the root causes
+    log::info!("Ranking most problematic dependencies");
+    let ranking = ranking::rank(&data_dir, &argv, &ranking_options)?;
+    let mut ranking_loc = data_dir.clone();
+    ranking_loc.push("mostproblematicdeps.json.new");
+    std::fs::write(&ranking_loc, serde_json::to_vec(&ranking)?)?;
+    let mut final_ranking_loc = data_dir.clone();
+    final_ranking_loc.push("mostproblematicdeps.json");
+    std::fs::rename(ranking_loc, final_ranking_loc)?;
+
     Ok(())
 }
 
+/// Returns all builds a depcache refers to. Its lines consist of the build of the root cause, the
+/// package name, the failed build and the chain of builds in between, which depcaches from before
+/// the chains were recorded don't have.
+fn dep_cache_builds(dep_cache: &str) -> Result<Vec<u64>> {
+    let mut builds = vec![];
+    for line in dep_cache.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() != 3 && parts.len() != 4 {
+            return Err(anyhow!("Invalid depcache line: {line}"));
+        }
+        builds.push(parts[0].parse::<u64>()?);
+        builds.push(parts[2].parse::<u64>()?);
+        if let Some(chain) = parts.get(3) {
+            for id in chain.split(',').filter(|id| !id.is_empty()) {
+                builds.push(id.parse::<u64>()?);
+            }
+        }
+    }
+    Ok(builds)
+}
+
+/// Reads the IDs of all builds of an evaluation that failed because of a dependency
+fn dependency_failed_builds_of(data_dir: &Path, eval: u64) -> Result<Vec<u64>> {
+    Ok(read_eval_cache(data_dir, eval)?
+        .into_iter()
+        .filter(|build| build.status == "Dependency failed")
+        .map(|build| build.build_id)
+        .collect())
+}
+
 /// Little error handling wrapper for `fetch_failed_deps_of`
 async fn fetch_failed_deps_of_wrapped(
     build_id: u64,
     file_to_write: Arc<Mutex<File>>,
     dep_cache_to_write: Arc<Mutex<File>>,
-    http_client: ClientWithMiddleware,
-    http_semaphore: Arc<Semaphore>,
+    build_pages: Arc<BuildPages>,
     wg_t: AsyncWaitGroup,
 ) {
-    if let Err(e) = fetch_failed_deps_of(
-        build_id,
-        file_to_write,
-        dep_cache_to_write,
-        http_client,
-        http_semaphore,
-    )
-    .await
+    if let Err(e) =
+        fetch_failed_deps_of(build_id, file_to_write, dep_cache_to_write, build_pages).await
     {
         log::error!("Failed fetching dependencies of build #{build_id}: {e}");
     }
@@ -236,93 +308,42 @@ async fn fetch_failed_deps_of(
     build_id: u64,
     file_to_write: Arc<Mutex<File>>,
     dep_cache_to_write: Arc<Mutex<File>>,
-    http_client: ClientWithMiddleware,
-    http_semaphore: Arc<Semaphore>,
+    build_pages: Arc<BuildPages>,
 ) -> Result<()> {
-    let mut lines_to_write = HashMap::new();
-    let mut dep_cache_line = String::new();
-    {
-        let permit = http_semaphore.acquire().await?;
-        let res = http_client
-            .get(format!("https://hydra.nixos.org/build/{build_id}"))
-            .send()
-            .await?
-            .text()
-            .await?;
-        drop(permit);
-        let doc = select::document::Document::from(&res[..]);
-
-        // Find architecture
-        let arch = doc
-            .find(Class("info-table").descendant(Name("tt")))
-            .next()
-            .ok_or_else(|| anyhow!("No architecture found"))?
-            .text();
-        log::debug!("Detected architecture {arch}");
+    let info = build_pages.get(build_id).await?;
 
-        // Find package name
-        let pkg_name = doc
-            .find(Attr("id", "tabs-details").descendant(Class("info-table").descendant(Name("tt"))))
-            .nth(2)
-            .ok_or_else(|| anyhow!("No package name found"))?
-            .text();
-        log::debug!("Detected package name {pkg_name}");
-
-        // Find all failed steps
-        let rows = doc
-            .find(
-                Attr("id", "tabs-buildsteps")
-                    .descendant(And(Name("table"), Class("clickable-rows"))),
-            )
-            .next()
-            .ok_or_else(|| anyhow!("No build steps found"))?
-            .find(Name("tr"));
-        for row in rows {
-            let cols: Vec<Node> = row.find(Name("td")).collect();
-            if cols.len() != 5 {
-                continue;
-            }
-            // Ignore non-failed steps
-            let status = cols[4].text();
-            if !status.contains("Failed") && !status.contains("Cached") {
-                continue;
-            }
-            // Find all links
-            let mut link_to_return = None;
-            for link in cols[4].find(Name("a")) {
-                // Use the log link
-                if link_to_return.is_none() && link.text() == "log" {
-                    link_to_return = link.attr("href");
-                }
-                // Prefer the propagated build link
-                if link.text().starts_with("build ") {
-                    link_to_return = link.attr("href");
-                }
-            }
-            if link_to_return.is_none() {
-                // This happens when a build is retried
-                continue;
-            }
-            // Calculate things to return
-            let store_path = cols[1]
-                .find(Name("tt"))
-                .next()
-                .ok_or_else(|| anyhow!("No store path found"))?
-                .text();
-            let store_path = store_path.split(',').next().unwrap();
-            let path_name = store_path[44..].to_owned();
-            let build_id_of_dependency = link_to_return
-                .ok_or_else(|| anyhow!("logic error"))?
-                .split('/')
-                .nth(4)
-                .ok_or_else(|| anyhow!("No build ID found"))?;
+    let mut lines_to_write = HashMap::new();
+    let mut dep_cache_lines = BTreeSet::new();
+    for step in &info.failed_steps {
+        let Some(root) = resolve_root_cause(build_id, step, &build_pages).await? else {
+            // Leave out the step rather than caching it with a wrong root cause
+            continue;
+        };
+        let mut outputs = StorePath::parse_outputs(&root.step.store_path)?;
+        outputs.sort();
+        let path_name = StorePath::derivation_name(&outputs)
+            .ok_or_else(|| anyhow!("Step has no outputs"))?
+            .to_owned();
+        let build_id_of_dependency = root.step.build_id;
+        let chain = root
+            .chain
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
 
-            lines_to_write.insert(
-                store_path.to_owned(),
-                format!("{path_name};{arch};{build_id_of_dependency}"),
-            );
-            dep_cache_line = format!("{build_id_of_dependency};{pkg_name};{build_id}");
-        }
+        let step_nr = root.step.step_nr.map_or(String::new(), |nr| nr.to_string());
+        lines_to_write.insert(
+            outputs,
+            format!(
+                "{path_name};{};{build_id_of_dependency};{step_nr}",
+                root.arch
+            ),
+        );
+        dep_cache_lines.insert(format!(
+            "{build_id_of_dependency};{};{build_id};{chain}",
+            info.pkg_name
+        ));
     }
 
     // Handle store path deduplication logic and write to file. We do this deduplication so we
@@ -335,11 +356,82 @@ async fn fetch_failed_deps_of(
             .write_all(format!("{line}\n").as_ref())
             .await?;
     }
-    dep_cache_to_write
-        .lock()
-        .await
-        .write_all(format!("{dep_cache_line}\n").as_ref())
-        .await?;
+    // A build may be blocked by multiple dependencies, each of them gets a line. They are written
+    // while holding the lock so the lines of a build stay together.
+    let mut dep_cache_to_write = dep_cache_to_write.lock().await;
+    for line in dep_cache_lines {
+        dep_cache_to_write
+            .write_all(format!("{line}\n").as_ref())
+            .await?;
+    }
 
     Ok(())
 }
+
+/// The build step that actually failed, found by following propagated failures
+struct RootCause {
+    step: FailedStep,
+    /// Architecture of the build the step failed in
+    arch: String,
+    /// All builds the failure was propagated through, ending with the build of `step`
+    chain: Vec<u64>,
+}
+
+/// Follows the propagated failure of a failed step until reaching the build where the step
+/// failed directly. Returns `None` if the failure loops or the chain is too long to follow, as the
+/// root cause is unknown then.
+async fn resolve_root_cause(
+    build_id: u64,
+    step: &FailedStep,
+    build_pages: &BuildPages,
+) -> Result<Option<RootCause>> {
+    let mut current = step.clone();
+    let mut arch = build_pages.get(build_id).await?.arch.clone();
+    let mut chain = vec![];
+    let mut seen = HashSet::from([build_id]);
+    // The step failed in the build itself
+    if current.build_id == build_id {
+        chain.push(build_id);
+    }
+    while current.build_id != build_id {
+        seen.insert(current.build_id);
+        chain.push(current.build_id);
+        if chain.len() > MAX_CHAIN_DEPTH {
+            log::warn!("Failure chain of build #{build_id} is too long, not caching it");
+            return Ok(None);
+        }
+        let dependency = build_pages.get(current.build_id).await?;
+        arch = dependency.arch.clone();
+        // Prefer the step building the same path, Hydra may show more failed steps
+        let next = dependency
+            .failed_steps
+            .iter()
+            .find(|s| s.store_path == current.store_path)
+            .or_else(|| dependency.failed_steps.first());
+        match next {
+            // The step failed in this build, so we found the root cause
+            Some(next) if next.build_id == current.build_id => {
+                current = next.clone();
+                break;
+            }
+            // The failure was propagated from a build that is already part of the chain, which
+            // may be the build itself
+            Some(next) if seen.contains(&next.build_id) => {
+                log::warn!(
+                    "Failure chain {chain:?} of build #{build_id} loops back to build #{}, not caching it",
+                    next.build_id
+                );
+                return Ok(None);
+            }
+            Some(next) => current = next.clone(),
+            // Nothing failed here (e.g. the build was restarted), so this is as far as we get
+            None => break,
+        }
+    }
+
+    Ok(Some(RootCause {
+        step: current,
+        arch,
+        chain,
+    }))
+}
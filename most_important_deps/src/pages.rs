@@ -0,0 +1,150 @@
+//! Access to Hydra build pages, going through the build cache
+
+use crate::buildcache::{BuildCache, BuildInfo, FailedStep};
+use anyhow::{anyhow, Result};
+use reqwest_middleware::ClientWithMiddleware;
+use select::node::Node;
+use select::predicate::{And, Attr, Class, Name, Predicate};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OnceCell, Semaphore};
+
+/// Hands out parsed build pages. Each build is fetched at most once per run, even when many tasks
+/// ask for the same build at the same time.
+pub struct BuildPages {
+    cache: BuildCache,
+    http_client: ClientWithMiddleware,
+    http_semaphore: Semaphore,
+    pages: Mutex<HashMap<u64, Arc<OnceCell<Arc<BuildInfo>>>>>,
+}
+
+impl BuildPages {
+    pub fn new(
+        cache: BuildCache,
+        http_client: ClientWithMiddleware,
+        parallel_requests: usize,
+    ) -> Self {
+        Self {
+            cache,
+            http_client,
+            http_semaphore: Semaphore::new(parallel_requests),
+            pages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the page of a build, either from the cache or from Hydra
+    pub async fn get(&self, build_id: u64) -> Result<Arc<BuildInfo>> {
+        let cell = self.pages.lock().await.entry(build_id).or_default().clone();
+        let info = cell
+            .get_or_try_init(|| async {
+                if let Some(info) = self.cache.get(build_id).await {
+                    log::debug!("Build #{build_id} is already cached");
+                    return Ok::<_, anyhow::Error>(Arc::new(info));
+                }
+                let info = fetch_build(build_id, &self.http_client, &self.http_semaphore).await?;
+                self.cache.put(build_id, &info).await?;
+                Ok(Arc::new(info))
+            })
+            .await?;
+        Ok(info.clone())
+    }
+}
+
+/// Fetches and parses the page of a build from Hydra
+async fn fetch_build(
+    build_id: u64,
+    http_client: &ClientWithMiddleware,
+    http_semaphore: &Semaphore,
+) -> Result<BuildInfo> {
+    let permit = http_semaphore.acquire().await?;
+    let res = http_client
+        .get(format!("https://hydra.nixos.org/build/{build_id}"))
+        .send()
+        .await?
+        .text()
+        .await?;
+    drop(permit);
+    let doc = select::document::Document::from(&res[..]);
+
+    // Find architecture
+    let arch = doc
+        .find(Class("info-table").descendant(Name("tt")))
+        .next()
+        .ok_or_else(|| anyhow!("No architecture found"))?
+        .text();
+    log::debug!("Detected architecture {arch}");
+
+    // Find package name
+    let pkg_name = doc
+        .find(Attr("id", "tabs-details").descendant(Class("info-table").descendant(Name("tt"))))
+        .nth(2)
+        .ok_or_else(|| anyhow!("No package name found"))?
+        .text();
+    log::debug!("Detected package name {pkg_name}");
+
+    // Find all failed steps
+    let mut failed_steps = vec![];
+    let rows = doc
+        .find(Attr("id", "tabs-buildsteps").descendant(And(Name("table"), Class("clickable-rows"))))
+        .next()
+        .ok_or_else(|| anyhow!("No build steps found"))?
+        .find(Name("tr"));
+    for row in rows {
+        let cols: Vec<Node> = row.find(Name("td")).collect();
+        if cols.len() != 5 {
+            continue;
+        }
+        // Ignore non-failed steps
+        let status = cols[4].text();
+        if !status.contains("Failed") && !status.contains("Cached") {
+            continue;
+        }
+        // Find all links
+        let mut log_link = None;
+        let mut build_link = None;
+        for link in cols[4].find(Name("a")) {
+            if link.text() == "log" {
+                log_link = link.attr("href");
+            }
+            if link.text().starts_with("build ") {
+                build_link = link.attr("href");
+            }
+        }
+        // Prefer the propagated build link
+        let link_to_return = if let Some(link) = build_link.or(log_link) {
+            link
+        } else {
+            // This happens when a build is retried
+            continue;
+        };
+        let store_path = cols[1]
+            .find(Name("tt"))
+            .next()
+            .ok_or_else(|| anyhow!("No store path found"))?
+            .text();
+        let build_id_of_dependency = link_to_return
+            .split('/')
+            .nth(4)
+            .ok_or_else(|| anyhow!("No build ID found"))?
+            .parse::<u64>()?;
+        // Log links look like https://hydra.nixos.org/build/<id>/nixlog/<step>
+        let step_nr = log_link.and_then(|link| {
+            let parts: Vec<&str> = link.split('/').collect();
+            if parts.get(4) != Some(&build_id_of_dependency.to_string().as_str()) {
+                return None;
+            }
+            parts.get(6)?.parse::<u64>().ok()
+        });
+        failed_steps.push(FailedStep {
+            store_path,
+            build_id: build_id_of_dependency,
+            step_nr,
+        });
+    }
+
+    Ok(BuildInfo {
+        arch,
+        pkg_name,
+        failed_steps,
+    })
+}
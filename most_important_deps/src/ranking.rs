@@ -0,0 +1,223 @@
+//! Ranking of the root causes that block the most builds.
+//!
+//! The result is written as JSON so the page renderer doesn't have to do any calculations.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use zhf_common::cache::{read_dep_cache, read_eval_cache, read_most_important_cache};
+
+/// Attribute prefix of NixOS tests in the NixOS jobset
+const NIXOS_TEST_PREFIX: &str = "nixos.tests.";
+
+/// How the ranking is calculated
+pub struct RankingOptions {
+    /// Number of root causes to emit
+    pub top: usize,
+    /// Weight of each blocked build by platform. Platforms that are not listed weigh 1.
+    pub platform_weights: HashMap<String, f64>,
+    /// Weight of blocked NixOS tests
+    pub nixos_test_weight: f64,
+    /// Attributes that block the channel
+    pub channel_blocking: HashSet<String>,
+    /// Weight of blocked attributes that block the channel
+    pub channel_blocking_weight: f64,
+}
+
+impl Default for RankingOptions {
+    fn default() -> Self {
+        Self {
+            top: 30,
+            platform_weights: HashMap::new(),
+            nixos_test_weight: 1.0,
+            channel_blocking: HashSet::new(),
+            channel_blocking_weight: 1.0,
+        }
+    }
+}
+
+impl RankingOptions {
+    fn weight_of(&self, attr: &str, system: &str) -> f64 {
+        let mut weight = self.platform_weights.get(system).copied().unwrap_or(1.0);
+        if attr.starts_with(NIXOS_TEST_PREFIX) {
+            weight *= self.nixos_test_weight;
+        }
+        if self.channel_blocking.contains(attr) {
+            weight *= self.channel_blocking_weight;
+        }
+        weight
+    }
+
+    /// Parses a `SYSTEM=WEIGHT` pair
+    pub fn add_platform_weight(&mut self, pair: &str) -> Result<()> {
+        let (system, weight) = pair
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Platform weight {pair} is not in the form SYSTEM=WEIGHT"))?;
+        self.platform_weights
+            .insert(system.to_string(), weight.parse::<f64>()?);
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct Ranking {
+    pub root_causes: Vec<RootCause>,
+}
+
+/// A failing derivation and everything it blocks
+#[derive(Serialize)]
+pub struct RootCause {
+    /// Name of the store path that fails to build
+    pub name: String,
+    /// Number of distinct blocked attributes
+    pub dependants: usize,
+    /// Weighted number of blocked attributes. Attributes that are blocked on multiple systems
+    /// count with the highest weight of these systems.
+    pub score: f64,
+    pub platforms: Vec<PlatformBreakdown>,
+    pub blocked: Vec<BlockedBuild>,
+}
+
+/// Blocked attributes of a root cause on a single platform
+#[derive(Serialize)]
+pub struct PlatformBreakdown {
+    pub system: String,
+    /// Build the root cause failed in
+    pub build_id: u64,
+    pub dependants: usize,
+}
+
+#[derive(Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockedBuild {
+    pub attr: String,
+    pub build_id: u64,
+    pub name: String,
+    pub system: String,
+    /// Names of the other root causes that block this build
+    pub also_blocked_by: Vec<String>,
+}
+
+/// Ranks the root causes of all given evaluations
+pub fn rank(data_dir: &Path, evals: &[u64], options: &RankingOptions) -> Result<Ranking> {
+    // Root cause build ID -> (names of the failed store paths, system). A build can fail in
+    // multiple store paths, each of them is a root cause of the builds blocked by the build.
+    let mut roots: HashMap<u64, (BTreeSet<String>, String)> = HashMap::new();
+    // Root cause build ID -> blocked builds
+    let mut blocked_by: HashMap<u64, BTreeSet<BlockedBuild>> = HashMap::new();
+    // Blocked build ID -> root cause build IDs
+    let mut roots_of: HashMap<u64, BTreeSet<u64>> = HashMap::new();
+    for eval in evals {
+        let eval_builds: HashMap<_, _> = read_eval_cache(data_dir, *eval)?
+            .into_iter()
+            .map(|b| (b.build_id, b))
+            .collect();
+        for root in read_most_important_cache(data_dir, *eval)? {
+            roots
+                .entry(root.build_id)
+                .or_insert_with(|| (BTreeSet::new(), root.arch))
+                .0
+                .insert(root.name);
+        }
+        for entry in read_dep_cache(data_dir, *eval)? {
+            let build = if let Some(build) = eval_builds.get(&entry.build_id) {
+                build
+            } else {
+                log::warn!("Build #{} is not part of eval {eval}", entry.build_id);
+                continue;
+            };
+            blocked_by
+                .entry(entry.root_build_id)
+                .or_default()
+                .insert(BlockedBuild {
+                    attr: build.attr.clone(),
+                    build_id: build.build_id,
+                    name: build.name.clone(),
+                    system: build.arch.clone(),
+                    also_blocked_by: vec![],
+                });
+            roots_of
+                .entry(entry.build_id)
+                .or_default()
+                .insert(entry.root_build_id);
+        }
+    }
+
+    // Group the root cause builds by the name of the failing store path, so failures of the
+    // same derivation on multiple platforms are ranked together
+    let mut by_name: BTreeMap<&str, Vec<(u64, &str)>> = BTreeMap::new();
+    for (build_id, (names, system)) in &roots {
+        for name in names {
+            by_name
+                .entry(name)
+                .or_default()
+                .push((*build_id, system.as_str()));
+        }
+    }
+    let mut root_causes = vec![];
+    for (name, builds) in by_name {
+        let mut platforms = vec![];
+        let mut blocked = BTreeSet::new();
+        for (build_id, system) in builds {
+            let blocked_here = if let Some(blocked_here) = blocked_by.get(&build_id) {
+                blocked_here
+            } else {
+                continue;
+            };
+            platforms.push(PlatformBreakdown {
+                system: system.to_string(),
+                build_id,
+                dependants: blocked_here
+                    .iter()
+                    .map(|b| &b.attr)
+                    .collect::<HashSet<_>>()
+                    .len(),
+            });
+            blocked.extend(blocked_here.iter().cloned());
+        }
+        if blocked.is_empty() {
+            continue;
+        }
+        platforms.sort_by(|a, b| a.system.cmp(&b.system).then(a.build_id.cmp(&b.build_id)));
+        // Weight of each blocked attribute, which is the highest one if it's blocked on multiple
+        // systems
+        let mut weights: BTreeMap<&str, f64> = BTreeMap::new();
+        for b in &blocked {
+            let weight = options.weight_of(&b.attr, &b.system);
+            weights
+                .entry(&b.attr)
+                .and_modify(|max| *max = max.max(weight))
+                .or_insert(weight);
+        }
+        root_causes.push(RootCause {
+            name: name.to_string(),
+            dependants: weights.len(),
+            score: weights.values().sum(),
+            platforms,
+            blocked: blocked
+                .into_iter()
+                .map(|mut b| {
+                    let other_roots = roots_of.get(&b.build_id).into_iter().flatten();
+                    b.also_blocked_by = other_roots
+                        .filter_map(|root| roots.get(root))
+                        .flat_map(|(other_names, _)| other_names)
+                        .filter(|other_name| *other_name != name)
+                        .cloned()
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect();
+                    b
+                })
+                .collect(),
+        });
+    }
+
+    root_causes.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.dependants.cmp(&a.dependants))
+            .then(a.name.cmp(&b.name))
+    });
+    root_causes.truncate(options.top);
+    Ok(Ranking { root_causes })
+}
@@ -0,0 +1,221 @@
+// Charts of the landing page. maintainer_pages embeds their data as JSON in #chart-data, except
+// for the history of the failure counts, which is loaded from data/burndown.json.
+var chartData = JSON.parse(document.getElementById('chart-data').textContent);
+
+// Merges from staging-next, drawn as dashed lines
+var stagingMerges = {};
+chartData.staging_merges.forEach(merge => {
+  stagingMerges['staging-' + merge.commit] = {
+    type: 'line',
+    borderColor: 'orange',
+    borderWidth: 2,
+    borderDash: [5,5],
+    scaleID: 'xAxis',
+    value: merge.time
+  };
+});
+
+// Series of burndown.json and how to draw them. The systems are hidden until they are selected
+// in the legend.
+var burndownSeries = [
+  { group: 'totals', name: 'linux', label: 'Linux Failures', color: '#4d6fb6', hidden: false },
+  { group: 'totals', name: 'darwin', label: 'Darwin Failures', color: '#7eb6e1', hidden: false },
+  { group: 'systems', name: 'x86_64-linux', label: 'x86_64-linux', color: '#2e4a86', hidden: true },
+  { group: 'systems', name: 'aarch64-linux', label: 'aarch64-linux', color: '#8aa2d6', hidden: true },
+  { group: 'systems', name: 'x86_64-darwin', label: 'x86_64-darwin', color: '#4e96c9', hidden: true },
+  { group: 'systems', name: 'aarch64-darwin', label: 'aarch64-darwin', color: '#b3d6f0', hidden: true },
+];
+
+var data = {
+  datasets: [{
+      label: 'Merges from staging-next to master',
+      borderColor: 'orange',
+      backgroundColor: 'orange',
+      data: []
+  }],
+};
+
+var burndownChart = new Chart(document.getElementById('burndown'), {
+  type: 'line',
+  data: data,
+  options: {
+    maintainAspectRatio: false,
+    interaction: {
+      mode: 'nearest',
+      intersect: false
+    },
+    animation: {
+      duration: 0,
+    },
+    responsiveAnimationDuration: 0,
+    backgroundColor: 'rgb(255, 255, 255)',
+    scales: {
+      xAxis: {
+        type: 'time',
+        adapters: {
+          time: {
+            zone: 'UTC',
+          },
+        },
+        grid: {
+          color: Chart.defaults.borderColor
+        },
+        ticks: {
+          color: Chart.defaults.color
+        },
+        distribution: 'series',
+        min: new Date(new Date().setDate(new Date().getDate()-14)), // 14 days ago
+        suggestedMax: '2024-12-01T00:00:00'
+      },
+      yAxis: {
+        ticks: {
+          min: 0,
+          color: Chart.defaults.color
+        },
+        grid: {
+          color: Chart.defaults.borderColor
+        },
+        min: 0
+      }
+    },
+    plugins: {
+      autocolors: false,
+      legend: {
+        display: true,
+        position: 'bottom',
+        color: Chart.defaults.color
+      },
+      annotation: {
+        annotations: Object.assign({
+          today: {
+            type: 'line',
+            value: new Date(),
+            borderColor: 'red',
+            borderWidth: 2,
+            scaleID: 'xAxis',
+            label: {
+              enabled: true,
+              content: 'Now',
+              position: 'end'
+            }
+          },
+          featurefreeze: {
+            type: 'line',
+            value: '2024-10-25T00:00:00',
+            borderColor: 'green',
+            borderWidth: 2,
+            borderDash: [5,5],
+            scaleID: 'xAxis',
+            label: {
+              enabled: true,
+              content: 'Feature Freeze',
+              position: 'start'
+            }
+          },
+          zhf: {
+            type: 'line',
+            value: '2024-10-30T00:00:00',
+            borderColor: 'green',
+            borderWidth: 2,
+            borderDash: [5,5],
+            scaleID: 'xAxis',
+            label: {
+              enabled: true,
+              content: 'ZHF',
+              position: 'start'
+            }
+          },
+          branchoff: {
+            type: 'line',
+            value: '2024-11-14T00:00:00',
+            borderColor: 'green',
+            borderWidth: 2,
+            borderDash: [5,5],
+            scaleID: 'xAxis',
+            label: {
+              enabled: true,
+              content: 'Branch-off',
+              position: 'start'
+            }
+          },
+          release: {
+            type: 'line',
+            value: '2024-11-22T00:00:00',
+            borderColor: 'green',
+            borderWidth: 2,
+            borderDash: [5,5],
+            scaleID: 'xAxis',
+            label: {
+              enabled: true,
+              content: 'Release',
+              position: 'start',
+            }
+          }
+        }, stagingMerges)
+      }
+    }
+  }
+});
+
+fetch('data/burndown.json')
+  .then(response => response.json())
+  .then(burndown => {
+    var datasets = burndownSeries.map(series => ({
+      label: series.label,
+      borderColor: series.color,
+      backgroundColor: series.color,
+      lineTension: 0,
+      hidden: series.hidden,
+      data: (burndown[series.group][series.name] || []).map(point => ({ x: point.time, y: point.total }))
+    }));
+    burndownChart.data.datasets = datasets.concat(burndownChart.data.datasets);
+    burndownChart.update();
+  });
+
+new Chart(document.getElementById('categories'), {
+  type: 'bar',
+  data: {
+    labels: chartData.categories.map(category => category.name),
+    datasets: [{
+      label: 'Direct failures',
+      borderColor: '#4d6fb6',
+      backgroundColor: '#4d6fb6',
+      data: chartData.categories.map(category => category.count)
+    }],
+  },
+  options: {
+    maintainAspectRatio: false,
+    animation: {
+      duration: 0,
+    },
+    plugins: {
+      legend: {
+        display: false
+      }
+    }
+  }
+});
+
+const colorSchemeQueryList = window.matchMedia('(prefers-color-scheme: dark)');
+const setColorScheme = e => {
+  var chart = Chart.getChart("burndown");
+  if (e.matches) {
+    // Dark
+    chart.options.scales.xAxis.ticks.color = "#fefefe";
+    chart.options.scales.yAxis.ticks.color = "#fefefe";
+    chart.options.plugins.legend.labels.color = "#fefefe";
+    chart.options.scales.xAxis.grid.color = "rgba(255, 255, 255, .1)";
+    chart.options.scales.yAxis.grid.color = "rgba(255, 255, 255, .1)";
+  } else {
+    // Light
+    chart.options.scales.xAxis.ticks.color = Chart.defaults.color;
+    chart.options.scales.yAxis.ticks.color = Chart.defaults.color;
+    chart.options.plugins.legend.labels.color = Chart.defaults.color;
+    chart.options.scales.xAxis.grid.color = Chart.defaults.borderColor;
+    chart.options.scales.yAxis.grid.color = Chart.defaults.borderColor;
+  }
+  chart.update()
+}
+
+setColorScheme(colorSchemeQueryList);
+colorSchemeQueryList.addEventListener('change', setColorScheme);
@@ -0,0 +1,132 @@
+// Client-side filtering of failed builds using the index written by maintainer_pages.
+//
+// The container of the search form (#search) names the index in data-index. If it has a
+// data-maintainer attribute, only builds of that maintainer are searched. The filters are kept
+// in the query string so searches can be shared.
+(function () {
+  'use strict';
+
+  const MAX_RESULTS = 500;
+  // Lists in the index the filters refer to
+  const LISTS = {
+    system: 'systems', status: 'statuses', category: 'categories', maintainer: 'maintainers',
+  };
+  const container = document.getElementById('search');
+  if (!container) {
+    return;
+  }
+  const form = container.querySelector('form');
+  const results = document.getElementById('search-results');
+  const failures = document.getElementById('failures');
+  const fixedMaintainer = container.dataset.maintainer;
+
+  function fillSelect(name, values, selected) {
+    const select = form.elements[name];
+    values
+      .map((value, i) => [value, i])
+      .sort((a, b) => a[0].localeCompare(b[0]))
+      .forEach(([value, i]) => {
+        const option = document.createElement('option');
+        option.value = String(i);
+        option.textContent = value;
+        option.selected = value === selected;
+        select.appendChild(option);
+      });
+  }
+
+  function cell(row, content) {
+    const td = document.createElement('td');
+    if (content instanceof Node) {
+      td.appendChild(content);
+    } else {
+      td.textContent = content;
+    }
+    row.appendChild(td);
+  }
+
+  function render(index, matches) {
+    results.textContent = '';
+    const summary = document.createElement('p');
+    summary.textContent = matches.length > MAX_RESULTS
+      ? `${matches.length} matching builds, showing the first ${MAX_RESULTS}.`
+      : `${matches.length} matching builds.`;
+    results.appendChild(summary);
+    const table = document.createElement('table');
+    const head = table.createTHead().insertRow();
+    for (const title of ['Attribute', 'Job name', 'Platform', 'Maintainers', 'Result', 'Reason']) {
+      const th = document.createElement('th');
+      th.textContent = title;
+      head.appendChild(th);
+    }
+    const body = table.createTBody();
+    for (const [attr, name, buildId, system, status, category, maintainers] of matches.slice(0, MAX_RESULTS)) {
+      const row = body.insertRow();
+      const link = document.createElement('a');
+      link.href = `https://hydra.nixos.org/build/${buildId}`;
+      link.textContent = attr;
+      cell(row, link);
+      cell(row, name);
+      cell(row, index.systems[system]);
+      cell(row, maintainers.map((m) => index.maintainers[m]).join(', '));
+      cell(row, index.statuses[status]);
+      cell(row, category < 0 ? '' : index.categories[category]);
+    }
+    results.appendChild(table);
+  }
+
+  function update(index) {
+    const query = form.elements.q.value.trim().toLowerCase();
+    const filters = Object.keys(LISTS).map((name) => [name, form.elements[name].value]);
+    // Shareable state
+    const params = new URLSearchParams();
+    if (query) {
+      params.set('q', query);
+    }
+    for (const [name, value] of filters) {
+      if (value !== '') {
+        params.set(name, index[LISTS[name]][Number(value)]);
+      }
+    }
+    const search = params.toString();
+    history.replaceState(null, '', search ? `?${search}` : location.pathname);
+
+    const active = query !== '' || filters.some(([, value]) => value !== '');
+    results.hidden = !active;
+    if (failures) {
+      failures.hidden = active;
+    }
+    if (!active) {
+      return;
+    }
+    const [system, status, category, maintainer] = filters.map(([, value]) => (value === '' ? null : Number(value)));
+    const fixed = fixedMaintainer === undefined ? -1 : index.maintainers.indexOf(fixedMaintainer);
+    const matches = index.builds.filter((build) => (
+      (query === '' || build[0].toLowerCase().includes(query) || build[1].toLowerCase().includes(query))
+      && (system === null || build[3] === system)
+      && (status === null || build[4] === status)
+      && (category === null || build[5] === category)
+      && (maintainer === null || build[6].includes(maintainer))
+      && (fixedMaintainer === undefined || build[6].includes(fixed))
+    ));
+    render(index, matches);
+  }
+
+  fetch(container.dataset.index)
+    .then((response) => response.json())
+    .then((index) => {
+      const params = new URLSearchParams(location.search);
+      form.elements.q.value = params.get('q') || '';
+      for (const [name, list] of Object.entries(LISTS)) {
+        fillSelect(name, index[list], params.get(name));
+      }
+      // The maintainer of a maintainer page is fixed
+      if (fixedMaintainer !== undefined) {
+        form.elements.maintainer.hidden = true;
+      }
+      form.addEventListener('input', () => update(index));
+      form.addEventListener('submit', (event) => event.preventDefault());
+      form.hidden = false;
+      update(index);
+    })
+    .catch((error) => console.error('Unable to load the search index', error));
+}());
@@ -8,6 +8,7 @@ import git
 from multiprocessing import Pool, Manager
 import subprocess
 import ast
+import json
 import sys
 
 
@@ -31,7 +32,29 @@ def clone_nixpkgs(rev, nixos):
     os.chdir(owd)
 
 
-def find_maintainer_for_job(job_name, nixos, res, job_maintainers):
+def fetch_teams():
+    """Returns the members and the short name of every team, keyed by the name of the team"""
+    expr = 'builtins.mapAttrs (_: t: { shortName = t.shortName; members = map (m: m.github or "_") t.members; })'
+    try:
+        return json.loads(subprocess.check_output(["nix", "eval", "--json", "-f", "./data/nixpkgs/lib", "teams", "--apply", expr], stderr=subprocess.DEVNULL).decode("utf-8"))
+    except Exception as _:
+        return {}
+
+
+def relative_position(position):
+    """Splits a meta.position into the file relative to the nixpkgs root and the line"""
+    (file, line) = position.rsplit(":", 1)
+    root = os.path.abspath("data/nixpkgs") + "/"
+    if file.startswith(root):
+        file = file[len(root):]
+    elif file.startswith("/nix/store/"):
+        file = "/".join(file.split("/")[4:])
+    else:
+        return None
+    return (file, int(line))
+
+
+def find_maintainer_for_job(job_name, nixos, res, job_maintainers, team_res, job_teams, position_res, job_positions):
     name_without_arch = ".".join(job_name.split(".")[:-1])
     real_job_name = job_name
     if not nixos:
@@ -51,6 +74,25 @@ def find_maintainer_for_job(job_name, nixos, res, job_maintainers):
     except Exception as _:
         res[job_name] = ["error"]
 
+    # Teams are optional, so jobs without them just don't belong to any team
+    try:
+        if name_without_arch not in job_teams.keys():
+            r = json.loads(subprocess.check_output(f"nix eval --json -f {file_to_evaluate} {real_job_name}.meta.teams --apply 'map (t: t.shortName)' 2> /dev/null", shell=True).decode("utf-8"))
+            job_teams[name_without_arch] = r
+        team_res[job_name] = job_teams[name_without_arch]
+    except Exception as _:
+        team_res[job_name] = []
+
+    # Positions are optional as well, jobs without one are just not linked to their source
+    try:
+        if name_without_arch not in job_positions.keys():
+            r = subprocess.check_output(f"nix eval --raw -f {file_to_evaluate} {real_job_name}.meta.position 2> /dev/null", shell=True).decode("utf-8")
+            job_positions[name_without_arch] = relative_position(r.strip())
+        if job_positions[name_without_arch] is not None:
+            position_res[job_name] = job_positions[name_without_arch]
+    except Exception as _:
+        job_positions[name_without_arch] = None
+
 
 
 def main(evals):
@@ -58,19 +100,26 @@ def main(evals):
         for ev in evals:
             res = mgr.dict({})
             job_maintainers = mgr.dict({})
+            team_res = mgr.dict({})
+            job_teams = mgr.dict({})
+            position_res = mgr.dict({})
+            job_positions = mgr.dict({})
 
             clone_nixpkgs(ev[1], ev[2])
             f = open(f"data/evalcache/{ev[0]}.cache")
             jobs = []
             jobs_info = {}
+            # Attributes as they appear in the evaluation cache, by job name
+            attrs = {}
             for line in f.readlines():
                 status  = line.split(" ")
                 if "failed" in status[-1].strip().lower():
                     job_name = status[0].strip()
                     if not ev[2]:
                         job_name = f"nixpkgs.{job_name}"
-                    jobs.append((job_name, ev[2], res, job_maintainers))
+                    jobs.append((job_name, ev[2], res, job_maintainers, team_res, job_teams, position_res, job_positions))
                     jobs_info[job_name] = status[1:]
+                    attrs[job_name] = status[0].strip()
             with Pool() as p:
                 p.starmap(find_maintainer_for_job, jobs)
 
@@ -83,6 +132,31 @@ def main(evals):
                         f.write(f"{maint['github']} {k} {' '.join(jobs_info[k])}")
                     else:
                         f.write(f"_ {k} {' '.join(jobs_info[k])}")
+            f.close()
+
+            # Teams are referenced by their short name, the cache uses the attribute name in lib.teams
+            teams = fetch_teams()
+            team_names = {t["shortName"]: name for (name, t) in teams.items()}
+            used_teams = set()
+            f = open(f"data/teamscache/{ev[0]}.cache", "w")
+            for (k, v) in team_res.items():
+                for short_name in v:
+                    team = team_names.get(short_name)
+                    if team is None:
+                        continue
+                    used_teams.add(team)
+                    f.write(f"{team} {k} {' '.join(jobs_info[k])}")
+            f.close()
+            f = open(f"data/teamscache/{ev[0]}.members", "w")
+            for team in sorted(used_teams):
+                members = ",".join(m for m in teams[team]["members"] if m != "_") or "-"
+                f.write(f"{team} {members} {teams[team]['shortName']}\n")
+            f.close()
+
+            f = open(f"data/positioncache/{ev[0]}.cache", "w")
+            for (k, (file, line)) in sorted(position_res.items()):
+                f.write(f"{attrs[k]} {file} {line}\n")
+            f.close()
 
 if __name__ == '__main__':
     args = sys.argv[1:]
@@ -36,9 +36,6 @@ read -r lastLinuxEvalNo lastLinuxEvalTime <<< "$(set -e; runRust crawl_jobset ni
 echo "Asking Hydra about nixpkgs..."
 read -r lastDarwinEvalNo lastDarwinEvalTime <<< "$(set -e; runRust crawl_jobset nixpkgs "${nixpkgsJobset}")"
 
-lastCheck="$(date --utc '+%Y-%m-%d %H:%M:%S (UTC)')"
-triggeredBy="${CI_PIPELINE_SOURCE:-???}"
-
 evalIdsUnsorted=("${lastLinuxEvalNo}" "${lastDarwinEvalNo}")
 IFS=$'\n' evalIds=($(sort <<<"${evalIdsUnsorted[*]}"))
 unset IFS
@@ -58,43 +55,11 @@ for evaluation in "${evalIds[@]}"; do
 done
 runRust crawl_evals "${args[@]}"
 
-
-echo "Calculating failing builds by platform..."
+# Failing builds by platform, as counted by crawl_evals
 declare -A systems
-mkdir -p data/failcache
-if ! [ -f "data/failcache/${evalIds[*]}.cache" ]; then
-	# Dedup by attrpath
-	declare -A builds
-	for evaluation in "${evalIds[@]}"; do
-		while read -r attrpath data; do
-			builds["$attrpath"]="$data"
-		done < "data/evalcache/${evaluation}.cache"
-	done
-	# Count by system
-	for val in "${builds[@]}"; do
-		if [ -z "${val}" ]; then
-			continue
-		fi
-		read -r _ _ system result <<< "${val}"
-		# Ignore cancelled jobs so cancelling an eval doesn't spike the graph
-		if [[ "${result}" = Succeeded ]] || [[ "${result}" = Cancelled ]]; then
-			continue
-		fi
-		if [ -v systems["${system}"] ]; then
-			systems["${system}"]=$((systems[$system] + 1))
-		else
-			systems["${system}"]=1
-		fi
-	done
-	for system in "${!systems[@]}"; do
-		echo "${system} ${systems["${system}"]}" >> "data/failcache/${evalIds[*]}.cache.new"
-	done
-	mv "data/failcache/${evalIds[*]}.cache.new" "data/failcache/${evalIds[*]}.cache"
-else
-	while IFS=' ' read -r system num; do
-		systems["${system}"]="${num}"
-	done < "data/failcache/${evalIds[*]}.cache"
-fi
+while IFS=' ' read -r system num; do
+	systems["${system}"]="${num}"
+done < "data/failcache/${evalIds[*]}.cache"
 # Clean cache
 for file in data/failcache/*; do
 	num="$(basename "${file}" .cache)"
@@ -104,16 +69,6 @@ for file in data/failcache/*; do
 	fi
 done
 
-# Calculate sums
-failingBuildsTable=
-totalBuildFailures=0
-IFS=$'\n' systemNamesSorted=($(sort <<<"${!systems[*]}"))
-unset IFS
-for system in "${systemNamesSorted[@]}"; do
-	failingBuildsTable+="<tr><td>Failing builds on ${system}:</td><td><b>${systems["${system}"]}</b></td></tr>"
-	totalBuildFailures="$((totalBuildFailures + ${systems["${system}"]}))"
-done
-
 # Insert historical data
 touch data/history-linux data/history-darwin
 if ! grep ^"${lastLinuxEvalNo} " data/history-linux; then
@@ -136,32 +91,13 @@ if ! grep ^"${lastDarwinEvalNo} " data/history-darwin; then
 	echo "${lastDarwinEvalNo} ${nFails} ${lastDarwinEvalTime}" >> data/history-darwin
 fi
 
-echo "Calculating charts..."
-linuxBurndown="$(
-	while read -r _ failed date; do
-		if [ -z "${failed}" ]; then
-			continue
-		fi
-		echo -n "{ x: '$(date -d "${date}" '+%Y-%m-%dT%H:%M:%S')', y: '${failed}' },"
-    done <<< "$(sort data/history-linux)"
-)"
-darwinBurndown="$(
-	while read -r _ failed date; do
-		if [ -z "${failed}" ]; then
-			continue
-		fi
-		echo -n "{ x: '$(date -d "${date}" '+%Y-%m-%dT%H:%M:%S')', y: '${failed}' },"
-    done <<< "$(sort data/history-darwin)"
-)"
-
 echo "Fetching maintainers..."
-declare -A maintainers
-mkdir -p data/maintainerscache
+mkdir -p data/maintainerscache data/teamscache data/positioncache
 args=()
-if [ ! -e "data/maintainerscache/${lastLinuxEvalNo}.cache" ] || [ ! -e "data/maintainerscache/${lastDarwinEvalNo}.cache" ]; then
+if [ ! -e "data/maintainerscache/${lastLinuxEvalNo}.cache" ] || [ ! -e "data/maintainerscache/${lastDarwinEvalNo}.cache" ] || [ ! -e "data/teamscache/${lastLinuxEvalNo}.cache" ] || [ ! -e "data/teamscache/${lastDarwinEvalNo}.cache" ] || [ ! -e "data/positioncache/${lastLinuxEvalNo}.cache" ] || [ ! -e "data/positioncache/${lastDarwinEvalNo}.cache" ]; then
 	for evaluation in "${evalIds[@]}"; do
-		if ! [ -f "data/maintainerscache/${evaluation}.cache" ]; then
-			nixpkgsCommit="$(curl -fsH 'Accept: application/json' "https://hydra.nixos.org/eval/${evaluation}" | jq -r .jobsetevalinputs.nixpkgs.revision)"
+		if ! [ -f "data/maintainerscache/${evaluation}.cache" ] || ! [ -f "data/teamscache/${evaluation}.cache" ] || ! [ -f "data/positioncache/${evaluation}.cache" ]; then
+			nixpkgsCommit="$(grep ^"${evaluation} " data/evalrevisions | cut -d' ' -f2)"
 			args+=("${evaluation}" "${nixpkgsCommit}")
 			if [[ "${evaluation}" = "${lastDarwinEvalNo}" ]]; then
 				args+=(0)
@@ -172,11 +108,6 @@ if [ ! -e "data/maintainerscache/${lastLinuxEvalNo}.cache" ] || [ ! -e "data/mai
 done
 ./scripts/fetch-maintainers.py "${args[@]}"
 fi
-for evaluation in "${evalIds[@]}"; do
-	while IFS=' ' read -r maint rest; do
-			maintainers["${maint}"]+=";${rest}"
-	done < "data/maintainerscache/${evaluation}.cache"
-done
 # Clean cache
 for file in data/maintainerscache/*; do
 	num="$(basename "${file}" .cache)"
@@ -185,62 +116,60 @@ for file in data/maintainerscache/*; do
 		rm "${file}"
 	fi
 done
+for file in data/teamscache/*; do
+	num="$(basename "${file}")"
+	num="${num%%.*}"
+	if [[ ! " ${evalIds[*]} " =~ " ${num} " ]]; then
+		echo "Purging teams cache of ${num}"
+		rm "${file}"
+	fi
+done
+for file in data/positioncache/*; do
+	num="$(basename "${file}" .cache)"
+	if [[ ! " ${evalIds[*]} " =~ " ${num} " ]]; then
+		echo "Purging position cache of ${num}"
+		rm "${file}"
+	fi
+done
 
 echo "Finding staging merges..."
 git --git-dir data/nixpkgs/.git fetch origin master
-if ! [[ -f data/staging-history ]]; then
-	# Some random blessed staging merge to prime the history
-	echo 'cf7f4393f3f953faf5765c7a0168c6710baa1423 1665443579' > data/staging-history
-fi
-lastStagingMerge="$(tail -n1 data/staging-history | cut -d' ' -f1)"
-git --git-dir data/nixpkgs/.git log --reverse "${lastStagingMerge}..origin/master" --grep='^staging-next ' --first-parent --format=%H\ %at >> data/staging-history
-stagingMerges=
-while IFS=' ' read -r hash date; do
-	stagingMerges+=", 'staging-${hash}': {"
-	stagingMerges+="type: 'line', borderColor: 'orange', borderWidth: 2, borderDash: [5,5], scaleID: 'xAxis', "
-	stagingMerges+="value: '$(date -d "@${date}" '+%Y-%m-%dT%H:%M:%S')'"
-	stagingMerges+="}"
-	stagingMerges+=$'\n'
-done < data/staging-history
-
-echo "Rendering maintainer pages..."
-runRust maintainer_pages "${evalIds[@]}"
+runRust zhf staging --repo data/nixpkgs --branch origin/master
+
+echo "Finding commits between evaluations..."
+for jobset in linux darwin; do
+	if [[ "${jobset}" = linux ]]; then
+		lastEvalNo="${lastLinuxEvalNo}"
+	else
+		lastEvalNo="${lastDarwinEvalNo}"
+	fi
+	previousEvalNo="$(cut -d' ' -f1 "data/history-${jobset}" | sort -n | grep -B1 -x "${lastEvalNo}" | head -n1)"
+	rm -f "data/evaldiff-${jobset}.json"
+	if [[ "${previousEvalNo}" = "${lastEvalNo}" ]] || ! [ -f "data/evalcache/${previousEvalNo}.cache" ] || ! grep -q ^"${previousEvalNo} " data/evalrevisions; then
+		echo "Not comparing ${lastEvalNo} without a known previous evaluation of ${jobset}"
+		continue
+	fi
+	runRust zhf commits --repo data/nixpkgs --output "data/evaldiff-${jobset}.json" "${previousEvalNo}" "${lastEvalNo}"
+done
 
 echo "Finding most important dependencies..."
 runRust most_important_deps "${evalIds[@]}"
 
-echo "Rendering most important builds..."
-lines="$(sort -n data/mostimportantcache/*.cache | uniq -c | sort -n | tail -n30 | tac | sed 's/^ *//g')"
-mostProblematicDeps=
-while IFS=' ' read -r count parts; do
-	IFS=';' read -r name system buildid <<< "${parts}"
-	mostProblematicDeps+="<tr><td><details><summary><a href=\"https://hydra.nixos.org/build/${buildid}\">${name}</a></summary><ul>"
-	mostProblematicDeps+="$(grep -h "^${buildid};" data/depcache/* | sort | awk -F ';' '{print "<li><a href=\"https://hydra.nixos.org/build/" $3 "\">" $2 "</a></li>"}' | tr -d '\n')" || :
-	mostProblematicDeps+="</ul></details></td><td>${system}</td><td>${count}</td></tr>"
-done <<< "${lines}"
-
-# Render page
+if [[ "${ZHF_FETCH_LOGS:-1}" = 1 ]]; then
+	echo "Fetching build logs..."
+	runRust crawl_logs "${evalIds[@]}"
+fi
+
+echo "Classifying failures..."
+runRust classify_failures "${evalIds[@]}"
+
+echo "Rendering pages..."
+runRust maintainer_pages --target-branch "${targetBranch}" --linux-eval "${lastLinuxEvalNo}" --darwin-eval "${lastDarwinEvalNo}" "${evalIds[@]}"
+
+if [[ -n "${ZHF_METRICS_FILE:-}" ]]; then
+	echo "Writing metrics..."
+	runRust zhf metrics --linux-eval "${lastLinuxEvalNo}" --darwin-eval "${lastDarwinEvalNo}" --output "${ZHF_METRICS_FILE}"
+fi
+
+# Copy static files
 cp -r page/* public/
-sed -i \
-	-e "s/@targetbranch@/${targetBranch}/g" \
-	-e "s/@lastlinuxevalno@/${lastLinuxEvalNo}/g" \
-	-e "s/@lastlinuxevaltime@/${lastLinuxEvalTime}/g" \
-	-e "s/@lastdarwinevalno@/${lastDarwinEvalNo}/g" \
-	-e "s/@lastdarwinevaltime@/${lastDarwinEvalTime}/g" \
-	-e "s/@totalbuildfailures@/${totalBuildFailures}/g" \
-	-e "s@failingbuildstable@${failingBuildsTable}g" \
-	-e "s/@linuxburndown@/${linuxBurndown}/g" \
-	-e "s/@darwinburndown@/${darwinBurndown}/g" \
-	-e "s/@lastcheck@/${lastCheck}/g" \
-	-e "s/@triggered@/${triggeredBy}/g" \
-	public/index.html
-
-echo "${stagingMerges}" | sed -i -e '/@stagingMerges@/{
-r /dev/stdin
-d
-}' public/index.html
-
-echo "${mostProblematicDeps}" | sed -i -e '/@mostproblematicdeps@/{
-r /dev/stdin
-d
-}' public/index.html
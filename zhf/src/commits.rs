@@ -0,0 +1,253 @@
+//! List the nixpkgs commits between two evaluations that could have broken jobs.
+//!
+//! The nixpkgs revisions of the evaluations are taken from `evalrevisions`, and the commits between
+//! them are read from a local nixpkgs repository, which has to contain both revisions. Attributes
+//! that fail in the newer evaluation but not in the older one count as newly broken. For each of
+//! them, the commits that touch the file of its `meta.position`, as recorded in `positioncache`,
+//! are the suspects. Merge commits are listed, but never suspected, as their changes are already
+//! attributed to the commits they merge.
+//!
+//! The report is printed, or written as JSON to a file for the eval diff page.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{TimeZone, Utc};
+use git2::{Repository, Sort};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{rename, File};
+use std::path::{Path, PathBuf};
+use zhf_common::cache::{data_dir, read_eval_revisions, read_failed_builds, read_position_cache};
+
+const USAGE: &str = "Usage: zhf commits [options] <old-eval> <new-eval>
+
+Options:
+  --repo <dir>     Local nixpkgs repository (default: data/nixpkgs)
+  --output <file>  Write the report as JSON to this file instead of printing it";
+
+#[derive(Serialize)]
+struct Report {
+    old_eval: u64,
+    new_eval: u64,
+    old_revision: String,
+    new_revision: String,
+    /// All commits in the range, newest first
+    commits: Vec<CommitInfo>,
+    newly_broken: Vec<BrokenAttr>,
+}
+
+#[derive(Serialize)]
+struct CommitInfo {
+    commit: String,
+    summary: String,
+    author: String,
+    /// Commit time as Unix timestamp
+    time: i64,
+}
+
+/// An attribute that fails in the newer evaluation only
+#[derive(Serialize)]
+struct BrokenAttr {
+    attr: String,
+    system: String,
+    build_id: u64,
+    /// File of `meta.position`, if known
+    file: Option<String>,
+    line: Option<u64>,
+    /// Commits touching the file, newest first
+    suspects: Vec<String>,
+}
+
+/// Commits touching each file, newest first
+type Touching = HashMap<String, Vec<String>>;
+
+pub fn run(args: &[String]) -> Result<()> {
+    let data_dir = data_dir()?;
+    let mut repo = data_dir.join("nixpkgs");
+    let mut output = None;
+    let mut evals = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--repo" => repo = PathBuf::from(value()?),
+            "--output" => output = Some(value()?.clone()),
+            _ => evals.push(
+                arg.parse::<u64>()
+                    .map_err(|_| anyhow!("Unexpected argument {arg}\n\n{USAGE}"))?,
+            ),
+        }
+    }
+    let [old_eval, new_eval] = evals[..] else {
+        return Err(anyhow!("Expected two evaluations\n\n{USAGE}"));
+    };
+
+    let revisions = read_eval_revisions(&data_dir)?;
+    let revision = |eval: u64| {
+        revisions
+            .get(&eval)
+            .cloned()
+            .ok_or_else(|| anyhow!("No nixpkgs revision of evaluation {eval} recorded"))
+    };
+    let old_revision = revision(old_eval)?;
+    let new_revision = revision(new_eval)?;
+
+    let failed_before: HashSet<String> = read_failed_builds(&data_dir, &[old_eval])?
+        .into_iter()
+        .map(|(_, build)| build.attr)
+        .collect();
+    let positions = read_position_cache(&data_dir, new_eval)?;
+    let mut newly_broken: Vec<_> = read_failed_builds(&data_dir, &[new_eval])?
+        .into_iter()
+        .filter(|(_, build)| !failed_before.contains(&build.attr))
+        .map(|(_, build)| {
+            let position = positions.get(&build.attr);
+            BrokenAttr {
+                file: position.map(|position| position.file.clone()),
+                line: position.map(|position| position.line),
+                attr: build.attr,
+                system: build.arch,
+                build_id: build.build_id,
+                suspects: vec![],
+            }
+        })
+        .collect();
+    if positions.is_empty() {
+        log::warn!("No positions of evaluation {new_eval} recorded");
+    }
+
+    let files: HashSet<&str> = newly_broken
+        .iter()
+        .filter_map(|broken| broken.file.as_deref())
+        .collect();
+    let (commits, touching) = walk(&repo, &old_revision, &new_revision, &files)?;
+    for broken in &mut newly_broken {
+        if let Some(suspects) = broken.file.as_ref().and_then(|file| touching.get(file)) {
+            broken.suspects = suspects.clone();
+        }
+    }
+
+    let report = Report {
+        old_eval,
+        new_eval,
+        old_revision,
+        new_revision,
+        commits,
+        newly_broken,
+    };
+    if let Some(output) = &output {
+        // Replace the file at once, so the page never sees half of it
+        let tmp = format!("{output}.new");
+        serde_json::to_writer(File::create(&tmp)?, &report)?;
+        rename(&tmp, output)?;
+        log::info!("Wrote the commits between {old_eval} and {new_eval} to {output}");
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+/// Lists the commits between two revisions, newest first, and which of them touch the files
+fn walk(
+    repo: &Path,
+    old: &str,
+    new: &str,
+    files: &HashSet<&str>,
+) -> Result<(Vec<CommitInfo>, Touching)> {
+    let repo = Repository::open(repo)
+        .with_context(|| format!("Unable to open repository {}", repo.display()))?;
+    let find = |revision: &str| {
+        repo.revparse_single(revision)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Unable to find {revision}, is the repository fetched?"))
+    };
+    let old = find(old)?;
+    let new = find(new)?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(new.id())?;
+    walk.hide(old.id())?;
+
+    let mut commits = vec![];
+    let mut touching = Touching::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let hash = commit.id().to_string();
+        if commit.parent_count() == 1 && !files.is_empty() {
+            let diff = repo.diff_tree_to_tree(
+                Some(&commit.parent(0)?.tree()?),
+                Some(&commit.tree()?),
+                None,
+            )?;
+            let mut touched = HashSet::new();
+            for delta in diff.deltas() {
+                for file in [delta.old_file().path(), delta.new_file().path()]
+                    .into_iter()
+                    .flatten()
+                {
+                    if let Some(file) = file.to_str().filter(|file| files.contains(file)) {
+                        touched.insert(file.to_string());
+                    }
+                }
+            }
+            for file in touched {
+                touching.entry(file).or_default().push(hash.clone());
+            }
+        }
+        commits.push(CommitInfo {
+            commit: hash,
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author: commit.author().name().unwrap_or_default().to_string(),
+            time: commit.time().seconds(),
+        });
+    }
+    Ok((commits, touching))
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "{} commits between evaluation {} ({}) and {} ({})",
+        report.commits.len(),
+        report.old_eval,
+        short(&report.old_revision),
+        report.new_eval,
+        short(&report.new_revision),
+    );
+    println!("{} newly failing attributes", report.newly_broken.len());
+    let commits: HashMap<&str, &CommitInfo> = report
+        .commits
+        .iter()
+        .map(|commit| (commit.commit.as_str(), commit))
+        .collect();
+    for broken in &report.newly_broken {
+        println!();
+        let (Some(file), Some(line)) = (&broken.file, broken.line) else {
+            println!("{}: position unknown", broken.attr);
+            continue;
+        };
+        println!("{} ({file}:{line})", broken.attr);
+        if broken.suspects.is_empty() {
+            println!("  no commits touching {file}");
+        }
+        for suspect in &broken.suspects {
+            let commit = commits[suspect.as_str()];
+            let time = Utc
+                .timestamp_opt(commit.time, 0)
+                .single()
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            println!(
+                "  {} {time} {} ({})",
+                short(suspect),
+                commit.summary,
+                commit.author
+            );
+        }
+    }
+}
+
+fn short(commit: &str) -> &str {
+    commit.get(..12).unwrap_or(commit)
+}
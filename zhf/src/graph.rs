@@ -0,0 +1,300 @@
+//! Export the graph of failed builds blocking other builds.
+//!
+//! The nodes are Hydra builds and there is an edge from each failed build to every build it
+//! blocks. This is built from `depcache` (which build was blocked by which root cause, and through
+//! which builds the failure was propagated), `mostimportantcache` (what the root causes are) and
+//! `evalcache` (attribute names and platforms).
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use zhf_common::cache::{
+    data_dir, read_dep_cache, read_eval_cache, read_most_important_cache, EvalBuild,
+};
+
+const USAGE: &str = "Usage: zhf graph [options] <eval>
+
+Options:
+  --format <dot|graphml|json>  Output format (default: dot)
+  --root-build <id>            Only export the builds blocked by this build
+  --root-attr <attr>           Only export the builds blocked by the build of this attribute
+  --output <file>              Write to this file instead of stdout";
+
+/// Supported output formats
+#[derive(Clone, Copy)]
+enum Format {
+    Dot,
+    GraphMl,
+    Json,
+}
+
+/// A build in the graph
+struct Node {
+    name: String,
+    attr: Option<String>,
+    system: Option<String>,
+    status: Option<String>,
+    /// Whether a build step failed in this build itself
+    root_cause: bool,
+}
+
+struct Graph {
+    eval: u64,
+    nodes: BTreeMap<u64, Node>,
+    /// Edges from the failed build to the blocked build
+    edges: BTreeSet<(u64, u64)>,
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let mut format = Format::Dot;
+    let mut root_build = None;
+    let mut root_attr = None;
+    let mut output = None;
+    let mut eval = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--format" => {
+                format = match value()?.as_str() {
+                    "dot" => Format::Dot,
+                    "graphml" => Format::GraphMl,
+                    "json" => Format::Json,
+                    other => return Err(anyhow!("Unknown format {other}\n\n{USAGE}")),
+                }
+            }
+            "--root-build" => root_build = Some(value()?.parse::<u64>()?),
+            "--root-attr" => root_attr = Some(value()?.clone()),
+            "--output" => output = Some(value()?.clone()),
+            _ if eval.is_none() => eval = Some(arg.parse::<u64>()?),
+            _ => return Err(anyhow!("Unexpected argument {arg}\n\n{USAGE}")),
+        }
+    }
+    let eval = eval.ok_or_else(|| anyhow!("No evaluation given\n\n{USAGE}"))?;
+
+    let data_dir = data_dir()?;
+    let eval_builds = read_eval_cache(&data_dir, eval)?;
+    let mut graph = Graph::new(eval);
+    {
+        let by_id: HashMap<u64, &EvalBuild> = eval_builds.iter().map(|b| (b.build_id, b)).collect();
+        for root in read_most_important_cache(&data_dir, eval)? {
+            let node = graph.node(root.build_id, &by_id);
+            node.name = root.name;
+            node.system = Some(root.arch);
+            node.root_cause = true;
+        }
+        for entry in read_dep_cache(&data_dir, eval)? {
+            graph.node(entry.build_id, &by_id).name = entry.pkg_name;
+            // The chain goes from the direct dependency to the root cause
+            let mut blocked = entry.build_id;
+            let chain = if entry.chain.is_empty() {
+                vec![entry.root_build_id]
+            } else {
+                entry.chain
+            };
+            for failed in chain {
+                graph.node(failed, &by_id);
+                graph.add_edge(failed, blocked);
+                blocked = failed;
+            }
+        }
+    }
+
+    if let Some(attr) = root_attr {
+        let build = eval_builds
+            .iter()
+            .find(|b| b.attr == attr)
+            .ok_or_else(|| anyhow!("Attribute {attr} is not part of evaluation {eval}"))?;
+        root_build = Some(build.build_id);
+    }
+    if let Some(root) = root_build {
+        if !graph.nodes.contains_key(&root) {
+            return Err(anyhow!("Build {root} does not block any builds"));
+        }
+        graph = graph.subtree(root);
+    }
+    log::info!(
+        "Graph has {} nodes and {} edges",
+        graph.nodes.len(),
+        graph.edges.len()
+    );
+
+    let rendered = match format {
+        Format::Dot => graph.to_dot(),
+        Format::GraphMl => graph.to_graphml(),
+        Format::Json => graph.to_json()?,
+    };
+    if let Some(output) = output {
+        File::create(output)?.write_all(rendered.as_bytes())?;
+    } else {
+        std::io::stdout().write_all(rendered.as_bytes())?;
+    }
+    Ok(())
+}
+
+impl Graph {
+    fn new(eval: u64) -> Self {
+        Self {
+            eval,
+            nodes: BTreeMap::new(),
+            edges: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the node of a build, creating it from the evaluation data if it doesn't exist yet
+    fn node(&mut self, build_id: u64, eval_builds: &HashMap<u64, &EvalBuild>) -> &mut Node {
+        self.nodes.entry(build_id).or_insert_with(|| {
+            let build = eval_builds.get(&build_id);
+            Node {
+                name: build.map_or_else(|| format!("build {build_id}"), |b| b.name.clone()),
+                attr: build.map(|b| b.attr.clone()),
+                system: build.map(|b| b.arch.clone()),
+                status: build.map(|b| b.status.clone()),
+                root_cause: false,
+            }
+        })
+    }
+
+    fn add_edge(&mut self, from: u64, to: u64) {
+        // Builds that failed themselves have themselves in their chain
+        if from != to {
+            self.edges.insert((from, to));
+        }
+    }
+
+    /// Only keeps the nodes reachable from `root`
+    fn subtree(mut self, root: u64) -> Self {
+        let mut reachable = BTreeSet::from([root]);
+        let mut queue = VecDeque::from([root]);
+        while let Some(current) = queue.pop_front() {
+            for (_, to) in self.edges.range((current, 0)..=(current, u64::MAX)) {
+                if reachable.insert(*to) {
+                    queue.push_back(*to);
+                }
+            }
+        }
+        self.nodes.retain(|id, _| reachable.contains(id));
+        self.edges
+            .retain(|(from, to)| reachable.contains(from) && reachable.contains(to));
+        self
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = format!("digraph \"eval-{}\" {{\n", self.eval);
+        for (id, node) in &self.nodes {
+            let mut label = node.attr.clone().unwrap_or_else(|| node.name.clone());
+            if let Some(system) = &node.system {
+                label.push_str(&format!("\n{system}"));
+            }
+            let shape = if node.root_cause { "box" } else { "ellipse" };
+            out.push_str(&format!(
+                "  \"{id}\" [label={}, shape={shape}, URL=\"https://hydra.nixos.org/build/{id}\"];\n",
+                dot_escape(&label)
+            ));
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!("  \"{from}\" -> \"{to}\";\n"));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_graphml(&self) -> String {
+        let mut out = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="attr" for="node" attr.name="attr" attr.type="string"/>
+  <key id="system" for="node" attr.name="system" attr.type="string"/>
+  <key id="status" for="node" attr.name="status" attr.type="string"/>
+  <key id="root_cause" for="node" attr.name="root_cause" attr.type="boolean"/>
+"#,
+        );
+        out.push_str(&format!(
+            "  <graph id=\"eval-{}\" edgedefault=\"directed\">\n",
+            self.eval
+        ));
+        for (id, node) in &self.nodes {
+            out.push_str(&format!("    <node id=\"{id}\">\n"));
+            out.push_str(&format!(
+                "      <data key=\"name\">{}</data>\n",
+                xml_escape(&node.name)
+            ));
+            for (key, value) in [
+                ("attr", &node.attr),
+                ("system", &node.system),
+                ("status", &node.status),
+            ] {
+                if let Some(value) = value {
+                    out.push_str(&format!(
+                        "      <data key=\"{key}\">{}</data>\n",
+                        xml_escape(value)
+                    ));
+                }
+            }
+            out.push_str(&format!(
+                "      <data key=\"root_cause\">{}</data>\n",
+                node.root_cause
+            ));
+            out.push_str("    </node>\n");
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!("    <edge source=\"{from}\" target=\"{to}\"/>\n"));
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    /// Node-link format as used by networkx and d3
+    fn to_json(&self) -> Result<String> {
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(id, node)| {
+                json!({
+                    "id": id,
+                    "name": node.name,
+                    "attr": node.attr,
+                    "system": node.system,
+                    "status": node.status,
+                    "root_cause": node.root_cause,
+                })
+            })
+            .collect();
+        let links: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(from, to)| json!({ "source": from, "target": to }))
+            .collect();
+        Ok(serde_json::to_string_pretty(&json!({
+            "directed": true,
+            "multigraph": false,
+            "graph": { "eval": self.eval },
+            "nodes": nodes,
+            "links": links,
+        }))?)
+    }
+}
+
+/// Quotes a string as DOT ID
+fn dot_escape(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
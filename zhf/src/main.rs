@@ -0,0 +1,35 @@
+//! Collection of tools working on the crawled data
+//!
+//! Usage: `zhf <command> [args...]`
+
+mod commits;
+mod graph;
+mod metrics;
+mod staging;
+
+use anyhow::{anyhow, Result};
+
+const USAGE: &str = "Usage: zhf <command> [args...]
+
+Commands:
+  commits  List the nixpkgs commits that could have broken jobs between two evaluations
+  graph    Export the graph of failed builds blocking other builds
+  metrics  Export failure metrics in the OpenMetrics format
+  staging  Find the merges of staging-next and what they broke";
+
+fn main() -> Result<()> {
+    env_logger::builder().format_timestamp(None).init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("commits") => commits::run(&args[1..]),
+        Some("graph") => graph::run(&args[1..]),
+        Some("metrics") => metrics::run(&args[1..]),
+        Some("staging") => staging::run(&args[1..]),
+        Some("help" | "--help" | "-h") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Some(cmd) => Err(anyhow!("Unknown command {cmd}\n\n{USAGE}")),
+        None => Err(anyhow!("No command given\n\n{USAGE}")),
+    }
+}
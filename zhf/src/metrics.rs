@@ -0,0 +1,284 @@
+//! Export failure metrics in the OpenMetrics text format.
+//!
+//! The metrics are computed from the same data as the landing page: the failed builds of the
+//! evaluation caches, the times of the evaluations from the history files and the statistics the
+//! crawlers write to `crawlstats`. They can be written to a file for the textfile collector of
+//! node-exporter, or served at `/metrics`, in which case the data is read again on every scrape.
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{read_dir, read_to_string};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use zhf_common::cache::{data_dir, read_failed_builds, read_history};
+
+const USAGE: &str = "Usage: zhf metrics [options] --linux-eval <eval> --darwin-eval <eval>
+
+Options:
+  --linux-eval <eval>   Latest evaluation of the Linux jobset
+  --darwin-eval <eval>  Latest evaluation of the Darwin jobset
+  --output <file>       Write the metrics to this file instead of stdout
+  --listen <addr>       Serve the metrics at /metrics on this address, like 127.0.0.1:9184";
+
+/// Content type of the OpenMetrics text format
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Statistics in `crawlstats` that are exported, with their help texts
+const CRAWL_STATS: &[(&str, &str)] = &[
+    ("finished_timestamp_seconds", "Time the last crawl finished"),
+    ("duration_seconds", "Duration of the last crawl"),
+    ("requests", "Requests sent to Hydra by the last crawl"),
+    (
+        "errors",
+        "Requests of the last crawl that failed or got an error status",
+    ),
+    (
+        "backoff_responses",
+        "Responses of the last crawl that asked to back off",
+    ),
+    (
+        "slow_responses",
+        "Responses of the last crawl slower than the latency target",
+    ),
+    (
+        "throttled_seconds",
+        "Time the last crawl waited for the rate limiter",
+    ),
+];
+
+pub fn run(args: &[String]) -> Result<()> {
+    let mut linux_eval = None;
+    let mut darwin_eval = None;
+    let mut output = None;
+    let mut listen = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--linux-eval" => linux_eval = Some(value()?.parse::<u64>()?),
+            "--darwin-eval" => darwin_eval = Some(value()?.parse::<u64>()?),
+            "--output" => output = Some(value()?.clone()),
+            "--listen" => listen = Some(value()?.clone()),
+            _ => return Err(anyhow!("Unexpected argument {arg}\n\n{USAGE}")),
+        }
+    }
+    let jobsets = [
+        (
+            "linux",
+            linux_eval.ok_or_else(|| anyhow!("No Linux evaluation given\n\n{USAGE}"))?,
+        ),
+        (
+            "darwin",
+            darwin_eval.ok_or_else(|| anyhow!("No Darwin evaluation given\n\n{USAGE}"))?,
+        ),
+    ];
+
+    let data_dir = data_dir()?;
+    if let Some(output) = &output {
+        // Replace the file at once, so the collector never reads half of it
+        let tmp = format!("{output}.tmp");
+        std::fs::write(&tmp, render(&data_dir, &jobsets)?)?;
+        std::fs::rename(&tmp, output)?;
+        log::info!("Wrote metrics to {output}");
+    } else if listen.is_none() {
+        print!("{}", render(&data_dir, &jobsets)?);
+    }
+    if let Some(listen) = listen {
+        serve(&listen, &data_dir, &jobsets)?;
+    }
+    Ok(())
+}
+
+/// Serves the metrics until the process is killed
+fn serve(addr: &str, data_dir: &Path, jobsets: &[(&str, u64)]) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("Serving metrics at http://{addr}/metrics");
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let mut request_line = String::new();
+        if let Err(e) = BufReader::new(&stream).read_line(&mut request_line) {
+            log::warn!("Failed to read request: {e}");
+            continue;
+        }
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => match render(data_dir, jobsets) {
+                Ok(body) => response("200 OK", CONTENT_TYPE, &body),
+                Err(e) => {
+                    log::error!("Failed to compute metrics: {e:#}");
+                    response(
+                        "500 Internal Server Error",
+                        "text/plain",
+                        "Failed to compute metrics\n",
+                    )
+                }
+            },
+            (Some("GET"), Some(_)) => response("404 Not Found", "text/plain", "Not found\n"),
+            _ => response(
+                "405 Method Not Allowed",
+                "text/plain",
+                "Method not allowed\n",
+            ),
+        };
+        if let Err(e) = stream.write_all(response.as_bytes()) {
+            log::warn!("Failed to send response: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Computes all metrics
+fn render(data_dir: &Path, jobsets: &[(&str, u64)]) -> Result<String> {
+    let mut out = String::new();
+
+    // Failed builds by system and status
+    let evals: Vec<u64> = jobsets.iter().map(|(_, eval)| *eval).collect();
+    let mut failed: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for (_, build) in read_failed_builds(data_dir, &evals)? {
+        *failed.entry((build.arch, build.status)).or_default() += 1;
+    }
+    family(
+        &mut out,
+        "zhf_failed_builds",
+        "Failed builds of the latest evaluations",
+    );
+    for ((system, status), count) in &failed {
+        sample(
+            &mut out,
+            "zhf_failed_builds",
+            &[("system", system), ("status", status)],
+            *count,
+        );
+    }
+
+    // Evaluations
+    family(
+        &mut out,
+        "zhf_eval_id",
+        "ID of the latest evaluation of the jobset",
+    );
+    for (jobset, eval) in jobsets {
+        sample(&mut out, "zhf_eval_id", &[("jobset", jobset)], eval);
+    }
+    let now = Utc::now().timestamp();
+    let mut times = vec![];
+    for (jobset, eval) in jobsets {
+        match eval_time(data_dir, jobset, *eval)? {
+            Some(time) => times.push((jobset, time)),
+            None => log::warn!("No time of evaluation {eval} found in history-{jobset}"),
+        }
+    }
+    family(
+        &mut out,
+        "zhf_eval_timestamp_seconds",
+        "Time of the latest evaluation of the jobset as shown by Hydra",
+    );
+    for (jobset, time) in &times {
+        sample(
+            &mut out,
+            "zhf_eval_timestamp_seconds",
+            &[("jobset", jobset)],
+            time,
+        );
+    }
+    family(
+        &mut out,
+        "zhf_eval_age_seconds",
+        "Age of the latest evaluation of the jobset",
+    );
+    for (jobset, time) in &times {
+        sample(
+            &mut out,
+            "zhf_eval_age_seconds",
+            &[("jobset", jobset)],
+            now - time,
+        );
+    }
+
+    // Crawls
+    let crawls = read_crawl_stats(data_dir)?;
+    for (key, help) in CRAWL_STATS {
+        let name = format!("zhf_crawl_{key}");
+        family(&mut out, &name, help);
+        for (crawler, stats) in &crawls {
+            if let Some(value) = stats.get(*key) {
+                sample(&mut out, &name, &[("crawler", crawler)], value);
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+    Ok(out)
+}
+
+/// Writes the metadata of a gauge
+fn family(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+/// Writes a sample of a gauge
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{key}=\"{value}\"")
+        })
+        .collect();
+    let _ = writeln!(out, "{name}{{{}}} {value}", labels.join(","));
+}
+
+/// Finds the time of an evaluation in the history of its jobset, as a Unix timestamp
+fn eval_time(data_dir: &Path, jobset: &str, eval: u64) -> Result<Option<i64>> {
+    Ok(read_history(data_dir, jobset)?
+        .into_iter()
+        .find(|entry| entry.eval == eval)
+        .and_then(|entry| entry.timestamp()))
+}
+
+/// Reads the statistics of the last crawl of each crawler
+fn read_crawl_stats(data_dir: &Path) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+    let mut crawls = BTreeMap::new();
+    let Ok(entries) = read_dir(data_dir.join("crawlstats")) else {
+        return Ok(crawls);
+    };
+    for entry in entries {
+        let entry = entry?;
+        let crawler = entry.file_name().to_string_lossy().into_owned();
+        let mut stats = BTreeMap::new();
+        for line in read_to_string(entry.path())?.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            // Don't pass anything but numbers on
+            if value.parse::<f64>().is_ok() {
+                stats.insert(key.to_string(), value.to_string());
+            }
+        }
+        crawls.insert(crawler, stats);
+    }
+    Ok(crawls)
+}
@@ -0,0 +1,282 @@
+//! Find the merges of staging-next and what they broke.
+//!
+//! The merges are found on the first-parent history of the target branch in a local nixpkgs
+//! repository, by the `staging-next YYYY-MM-DD` line in their commit message, and appended to
+//! `staging-history` as lines of the commit and its commit time. The repository has to be fetched
+//! beforehand.
+//!
+//! For the most recent merges, the last evaluation of each jobset without the merge is compared to
+//! the first one that contains it. Whether an evaluation contains a merge is decided by its nixpkgs
+//! revision from `evalrevisions`, so only evaluations with a recorded revision that is part of the
+//! repository are considered. The failed builds per system and the attributes that failed after
+//! the merge but not before it are written to `stagingimpact.json`. Attributes that are new in the
+//! later evaluation count as newly broken. Merges without an evaluation after them, or whose
+//! evaluations are no longer cached, have no impact yet.
+
+use anyhow::{anyhow, Context, Result};
+use git2::{Oid, Repository};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{read_to_string, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zhf_common::cache::{
+    cache_file, data_dir, read_eval_revisions, read_failed_builds, read_history, HistoryEntry,
+};
+
+const USAGE: &str = "Usage: zhf staging [options]
+
+Options:
+  --repo <dir>      Local nixpkgs repository (default: data/nixpkgs)
+  --branch <ref>    Branch staging-next is merged into (default: origin/master)
+  --merges <n>      Number of recent merges to compute the impact of (default: 10)";
+
+/// Merge that starts the history if there is none yet
+const FIRST_MERGE: &str = "cf7f4393f3f953faf5765c7a0168c6710baa1423 1665443579";
+/// Jobsets the impact is computed for
+const JOBSETS: &[&str] = &["linux", "darwin"];
+
+/// A merge of staging-next, as found in `staging-history`
+struct Merge {
+    commit: String,
+    /// Commit time as Unix timestamp
+    time: i64,
+}
+
+#[derive(Serialize)]
+struct Impacts {
+    /// Most recent merges first
+    merges: Vec<Impact>,
+}
+
+/// What a merge of staging-next changed
+#[derive(Serialize)]
+struct Impact {
+    commit: String,
+    time: i64,
+    evals: Vec<EvalPair>,
+    systems: Vec<SystemDelta>,
+    newly_broken: Vec<BrokenAttr>,
+}
+
+/// The evaluations of a jobset around a merge
+#[derive(Serialize)]
+struct EvalPair {
+    jobset: &'static str,
+    before: Option<u64>,
+    after: Option<u64>,
+}
+
+/// Failed builds of a system before and after a merge
+#[derive(Serialize)]
+struct SystemDelta {
+    system: String,
+    before: usize,
+    after: usize,
+}
+
+/// An attribute that fails since a merge
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct BrokenAttr {
+    attr: String,
+    system: String,
+    build_id: u64,
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let data_dir = data_dir()?;
+    let mut repo = data_dir.join("nixpkgs");
+    let mut branch = "origin/master".to_string();
+    let mut num_merges = 10;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--repo" => repo = PathBuf::from(value()?),
+            "--branch" => branch = value()?.clone(),
+            "--merges" => num_merges = value()?.parse::<usize>()?,
+            _ => return Err(anyhow!("Unexpected argument {arg}\n\n{USAGE}")),
+        }
+    }
+
+    let history_loc = data_dir.join("staging-history");
+    if !history_loc.exists() {
+        std::fs::write(&history_loc, format!("{FIRST_MERGE}\n"))?;
+    }
+    let mut merges = read_merges(&history_loc)?;
+    let last = merges
+        .last()
+        .ok_or_else(|| anyhow!("staging-history is empty"))?;
+    let repo = Repository::open(&repo)
+        .with_context(|| format!("Unable to open repository {}", repo.display()))?;
+    let new_merges = find_merges(&repo, &branch, &last.commit)?;
+    log::info!("Found {} new merges of staging-next", new_merges.len());
+    let mut history = OpenOptions::new().append(true).open(&history_loc)?;
+    for merge in &new_merges {
+        writeln!(history, "{} {}", merge.commit, merge.time)?;
+    }
+    merges.extend(new_merges);
+
+    let histories = JOBSETS
+        .iter()
+        .map(|jobset| Ok((*jobset, read_history(&data_dir, jobset)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let revisions = read_eval_revisions(&data_dir)?;
+    let merges = merges
+        .iter()
+        .rev()
+        .take(num_merges)
+        .map(|merge| impact(&data_dir, &repo, &histories, &revisions, merge))
+        .collect::<Result<_>>()?;
+
+    // Write to a temporary file first, so the page never sees half of it
+    let loc = data_dir.join("stagingimpact.json");
+    let tmp_loc = data_dir.join("stagingimpact.json.new");
+    serde_json::to_writer(File::create(&tmp_loc)?, &Impacts { merges })?;
+    rename(tmp_loc, loc)?;
+    Ok(())
+}
+
+/// Reads `staging-history`, oldest merge first
+fn read_merges(loc: &Path) -> Result<Vec<Merge>> {
+    let mut merges = vec![];
+    for line in read_to_string(loc)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let (commit, time) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid staging-history line: {line}"))?;
+        merges.push(Merge {
+            commit: commit.to_string(),
+            time: time.parse::<i64>()?,
+        });
+    }
+    Ok(merges)
+}
+
+/// Finds the merges of staging-next into a branch since a merge, oldest first
+fn find_merges(repo: &Repository, branch: &str, since: &str) -> Result<Vec<Merge>> {
+    let head = repo
+        .revparse_single(branch)
+        .with_context(|| format!("Unable to find {branch}"))?
+        .peel_to_commit()?;
+    let mut walk = repo.revwalk()?;
+    walk.simplify_first_parent()?;
+    walk.push(head.id())?;
+    walk.hide(Oid::from_str(since)?)
+        .with_context(|| format!("Unable to find the last known merge {since}"))?;
+    let mut merges = vec![];
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let is_staging_merge = commit.message().is_some_and(|message| {
+            message
+                .lines()
+                .any(|line| line.starts_with("staging-next "))
+        });
+        if is_staging_merge {
+            merges.push(Merge {
+                commit: commit.id().to_string(),
+                time: commit.time().seconds(),
+            });
+        }
+    }
+    merges.reverse();
+    Ok(merges)
+}
+
+/// Compares the evaluations around a merge
+fn impact(
+    data_dir: &Path,
+    repo: &Repository,
+    histories: &[(&'static str, Vec<HistoryEntry>)],
+    revisions: &HashMap<u64, String>,
+    merge: &Merge,
+) -> Result<Impact> {
+    let merge_oid = Oid::from_str(&merge.commit)?;
+    let mut evals = vec![];
+    let mut systems: BTreeMap<String, SystemDelta> = BTreeMap::new();
+    let mut newly_broken = BTreeSet::new();
+    for (jobset, history) in histories {
+        // Go back from the latest evaluation until one doesn't contain the merge
+        let mut before = None;
+        let mut after = None;
+        for entry in history.iter().rev() {
+            let Some(revision) = revisions.get(&entry.eval) else {
+                continue;
+            };
+            match contains(repo, revision, merge_oid) {
+                Ok(true) => after = Some(entry.eval),
+                Ok(false) => {
+                    before = Some(entry.eval);
+                    break;
+                }
+                Err(e) => log::warn!("Skipping evaluation {} of {jobset}: {e:#}", entry.eval),
+            }
+        }
+        evals.push(EvalPair {
+            jobset,
+            before,
+            after,
+        });
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+        if !cache_file(data_dir, "evalcache", before).exists()
+            || !cache_file(data_dir, "evalcache", after).exists()
+        {
+            log::warn!(
+                "Evaluations {before} and {after} around {} are not cached",
+                merge.commit
+            );
+            continue;
+        }
+        let failed_before: HashMap<String, String> = read_failed_builds(data_dir, &[before])?
+            .into_iter()
+            .map(|(_, build)| (build.attr, build.arch))
+            .collect();
+        for (_, build) in read_failed_builds(data_dir, &[after])? {
+            if !failed_before.contains_key(&build.attr) {
+                newly_broken.insert(BrokenAttr {
+                    attr: build.attr.clone(),
+                    system: build.arch.clone(),
+                    build_id: build.build_id,
+                });
+            }
+            delta(&mut systems, &build.arch).after += 1;
+        }
+        for system in failed_before.values() {
+            delta(&mut systems, system).before += 1;
+        }
+    }
+    Ok(Impact {
+        commit: merge.commit.clone(),
+        time: merge.time,
+        evals,
+        systems: systems.into_values().collect(),
+        newly_broken: newly_broken.into_iter().collect(),
+    })
+}
+
+/// Whether a revision contains a commit
+fn contains(repo: &Repository, revision: &str, commit: Oid) -> Result<bool> {
+    let revision = Oid::from_str(revision)?;
+    if revision == commit {
+        return Ok(true);
+    }
+    repo.graph_descendant_of(revision, commit)
+        .with_context(|| format!("Unable to find {revision}, is the repository fetched?"))
+}
+
+fn delta<'a>(systems: &'a mut BTreeMap<String, SystemDelta>, system: &str) -> &'a mut SystemDelta {
+    systems
+        .entry(system.to_string())
+        .or_insert_with(|| SystemDelta {
+            system: system.to_string(),
+            before: 0,
+            after: 0,
+        })
+}
@@ -0,0 +1,298 @@
+//! Readers for the cache files in the `data` directory
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// Returns the data directory, which is `data` in the current working directory
+pub fn data_dir() -> Result<PathBuf> {
+    let mut data_dir = std::env::current_dir()?;
+    data_dir.push("data");
+    Ok(data_dir)
+}
+
+/// Returns the location of the cache file of an evaluation in a cache directory
+pub fn cache_file(data_dir: &Path, cache: &str, eval: u64) -> PathBuf {
+    let mut loc = data_dir.to_path_buf();
+    loc.push(cache);
+    loc.push(format!("{eval}.cache"));
+    loc
+}
+
+/// A job of an evaluation, as found in `evalcache`
+pub struct EvalBuild {
+    pub attr: String,
+    pub build_id: u64,
+    pub name: String,
+    pub arch: String,
+    pub status: String,
+}
+
+impl EvalBuild {
+    /// Whether the build itself failed, rather than one of its dependencies
+    pub fn is_direct_failure(&self) -> bool {
+        !["Succeeded", "Dependency failed", "Cancelled"].contains(&self.status.as_str())
+    }
+}
+
+/// Reads all jobs of an evaluation from `evalcache`
+pub fn read_eval_cache(data_dir: &Path, eval: u64) -> Result<Vec<EvalBuild>> {
+    let mut builds = vec![];
+    for line in read_to_string(cache_file(data_dir, "evalcache", eval))?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(5, ' ').collect();
+        if parts.len() != 5 {
+            return Err(anyhow!("Invalid evalcache line: {line}"));
+        }
+        builds.push(EvalBuild {
+            attr: parts[0].to_string(),
+            build_id: parts[1].parse::<u64>()?,
+            name: parts[2].to_string(),
+            arch: parts[3].to_string(),
+            status: parts[4].to_string(),
+        });
+    }
+    Ok(builds)
+}
+
+/// Returns the attribute of a job without the `nixpkgs.` prefix, which the maintainers cache adds to
+/// the jobs of the nixpkgs jobset but the evaluation cache doesn't. Attributes from both caches are
+/// compared by this key. Jobs of the nixos jobset that have the prefix don't clash with the ones of
+/// the nixpkgs jobset, as they are built for other systems.
+pub fn job_key(attr: &str) -> &str {
+    attr.strip_prefix("nixpkgs.").unwrap_or(attr)
+}
+
+/// Reads the failed jobs of some evaluations the way the landing page counts them: every
+/// attribute once, taken from the last evaluation it's part of, and without succeeded or cancelled
+/// jobs so cancelling an evaluation doesn't spike the counts. Returns the jobs ordered by
+/// attribute, together with their evaluation.
+pub fn read_failed_builds(data_dir: &Path, evals: &[u64]) -> Result<Vec<(u64, EvalBuild)>> {
+    let mut builds = BTreeMap::new();
+    for eval in evals {
+        for build in read_eval_cache(data_dir, *eval)? {
+            builds.insert(build.attr.clone(), (*eval, build));
+        }
+    }
+    Ok(builds
+        .into_values()
+        .filter(|(_, build)| build.status != "Succeeded" && build.status != "Cancelled")
+        .collect())
+}
+
+/// An evaluation in the failure history of a jobset
+pub struct HistoryEntry {
+    pub eval: u64,
+    /// Number of failed builds of the jobset
+    pub failed: usize,
+    /// Time of the evaluation as shown by Hydra, like `2023-05-02 10:06:44 (UTC)`
+    pub time: String,
+}
+
+impl HistoryEntry {
+    /// Returns the time of the evaluation as Unix timestamp, if it can be parsed
+    pub fn timestamp(&self) -> Option<i64> {
+        let time = self.time.split(" (").next()?.trim();
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .map(|time| Utc.from_utc_datetime(&time).timestamp())
+    }
+}
+
+/// Reads the failure history of a jobset (`linux` or `darwin`) from `history-{jobset}`, ordered by
+/// evaluation. The history is empty before the first run.
+pub fn read_history(data_dir: &Path, jobset: &str) -> Result<Vec<HistoryEntry>> {
+    let loc = data_dir.join(format!("history-{jobset}"));
+    if !loc.exists() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for line in read_to_string(loc)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+        if parts.len() != 3 {
+            return Err(anyhow!("Invalid history-{jobset} line: {line}"));
+        }
+        entries.push(HistoryEntry {
+            eval: parts[0].parse::<u64>()?,
+            failed: parts[1].parse::<usize>()?,
+            time: parts[2].to_string(),
+        });
+    }
+    entries.sort_by_key(|entry| entry.eval);
+    Ok(entries)
+}
+
+/// Reads the nixpkgs revisions the evaluations were built from, which are recorded in
+/// `evalrevisions` as lines of the evaluation and the revision
+pub fn read_eval_revisions(data_dir: &Path) -> Result<HashMap<u64, String>> {
+    let mut revisions = HashMap::new();
+    let loc = data_dir.join("evalrevisions");
+    if !loc.exists() {
+        return Ok(revisions);
+    }
+    for line in read_to_string(loc)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let (eval, revision) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid evalrevisions line: {line}"))?;
+        revisions.insert(eval.parse::<u64>()?, revision.to_string());
+    }
+    Ok(revisions)
+}
+
+/// Where an attribute is defined, from its `meta.position`
+pub struct Position {
+    /// Path of the file relative to the root of nixpkgs
+    pub file: String,
+    pub line: u64,
+}
+
+/// Reads the positions of the failed jobs of an evaluation from `positioncache`, by attribute.
+/// Evaluations whose maintainers were fetched before positions were recorded have no positions.
+pub fn read_position_cache(data_dir: &Path, eval: u64) -> Result<HashMap<String, Position>> {
+    let mut positions = HashMap::new();
+    let loc = cache_file(data_dir, "positioncache", eval);
+    if !loc.exists() {
+        return Ok(positions);
+    }
+    for line in read_to_string(loc)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+        if parts.len() != 3 {
+            return Err(anyhow!("Invalid positioncache line: {line}"));
+        }
+        positions.insert(
+            parts[0].to_string(),
+            Position {
+                file: parts[1].to_string(),
+                line: parts[2].parse::<u64>()?,
+            },
+        );
+    }
+    Ok(positions)
+}
+
+/// Returns the location of the cached log tail of a build
+pub fn log_file(data_dir: &Path, build_id: u64) -> PathBuf {
+    let mut loc = data_dir.to_path_buf();
+    loc.push("logcache");
+    loc.push(format!("{build_id}.log"));
+    loc
+}
+
+/// Reads the categories of the direct failures of an evaluation from `classcache`, by build ID.
+/// Evaluations that were not classified have no categories.
+pub fn read_class_cache(data_dir: &Path, eval: u64) -> Result<HashMap<u64, String>> {
+    let mut categories = HashMap::new();
+    let loc = cache_file(data_dir, "classcache", eval);
+    if !loc.exists() {
+        return Ok(categories);
+    }
+    for line in read_to_string(loc)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let (build_id, category) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid classcache line: {line}"))?;
+        categories.insert(build_id.parse::<u64>()?, category.to_string());
+    }
+    Ok(categories)
+}
+
+/// A build that failed because of a dependency, as found in `depcache`.
+/// Builds with multiple failed dependencies have one entry per dependency.
+pub struct DepCacheEntry {
+    /// Build that caused the failure
+    pub root_build_id: u64,
+    /// Package name of the failed build
+    pub pkg_name: String,
+    /// The failed build
+    pub build_id: u64,
+    /// Builds the failure was propagated through, from the direct dependency to the root cause
+    pub chain: Vec<u64>,
+}
+
+/// Reads all entries of an evaluation from `depcache`
+pub fn read_dep_cache(data_dir: &Path, eval: u64) -> Result<Vec<DepCacheEntry>> {
+    let mut entries = vec![];
+    for line in read_to_string(cache_file(data_dir, "depcache", eval))?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() < 3 {
+            return Err(anyhow!("Invalid depcache line: {line}"));
+        }
+        let chain = if let Some(chain) = parts.get(3) {
+            chain
+                .split(',')
+                .filter(|id| !id.is_empty())
+                .map(str::parse::<u64>)
+                .collect::<Result<_, _>>()?
+        } else {
+            vec![]
+        };
+        entries.push(DepCacheEntry {
+            root_build_id: parts[0].parse::<u64>()?,
+            pkg_name: parts[1].to_string(),
+            build_id: parts[2].parse::<u64>()?,
+            chain,
+        });
+    }
+    Ok(entries)
+}
+
+/// The root cause of a failure, as found in `mostimportantcache`
+pub struct RootCauseEntry {
+    /// Name of the store path that failed to build
+    pub name: String,
+    pub arch: String,
+    /// Build the store path failed in
+    pub build_id: u64,
+    /// Step of the build the store path failed in, if known
+    pub step_nr: Option<u64>,
+}
+
+/// Reads all entries of an evaluation from `mostimportantcache`
+pub fn read_most_important_cache(data_dir: &Path, eval: u64) -> Result<Vec<RootCauseEntry>> {
+    let mut entries = vec![];
+    for line in read_to_string(cache_file(data_dir, "mostimportantcache", eval))?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() != 3 && parts.len() != 4 {
+            return Err(anyhow!("Invalid mostimportantcache line: {line}"));
+        }
+        entries.push(RootCauseEntry {
+            name: parts[0].to_string(),
+            arch: parts[1].to_string(),
+            build_id: parts[2].parse::<u64>()?,
+            step_nr: match parts.get(3) {
+                Some(nr) if !nr.is_empty() => Some(nr.parse::<u64>()?),
+                _ => None,
+            },
+        });
+    }
+    Ok(entries)
+}
+
+/// Returns the location of the cached log tail of a single build step
+pub fn step_log_file(data_dir: &Path, build_id: u64, step_nr: u64) -> PathBuf {
+    let mut loc = data_dir.to_path_buf();
+    loc.push("logcache");
+    loc.push(format!("{build_id}-{step_nr}.log"));
+    loc
+}
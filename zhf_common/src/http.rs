@@ -0,0 +1,285 @@
+//! HTTP client for talking to Hydra.
+//!
+//! All requests go through a shared [`RateLimiter`], a token bucket whose rate is adjusted with
+//! AIMD: it grows slowly while Hydra answers quickly and is cut in half when Hydra is slow or
+//! tells us to back off (429/503). `Retry-After` headers pause all requests for the given time.
+//!
+//! The limiter is configured with these environment variables:
+//! - `ZHF_REQUESTS_PER_SECOND`: Maximum (and initial) number of requests per second
+//! - `ZHF_LATENCY_TARGET_MS`: Responses slower than this reduce the rate
+//!
+//! At the end of a crawl, the statistics of the limiter are written to `crawlstats/{crawler}` in
+//! the data directory, with one `key value` line per statistic, so they can be monitored.
+
+use anyhow::Result;
+use reqwest::{header::RETRY_AFTER, Request, Response, StatusCode};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next};
+use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use task_local_extensions::Extensions;
+use tokio::time::{sleep, Instant};
+
+/// User agent of all crawlers
+const USER_AGENT: &str = "zh.fail scraper, please contact @dasJ on GitHub";
+/// Default maximum number of requests per second
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 4.0;
+/// Default latency above which the rate is reduced
+const DEFAULT_LATENCY_TARGET: Duration = Duration::from_secs(10);
+/// The rate never drops below this many requests per second
+const MIN_REQUESTS_PER_SECOND: f64 = 0.1;
+/// Requests per second added for every fast response
+const ADDITIVE_INCREASE: f64 = 0.05;
+/// Factor the rate is multiplied with when Hydra is struggling
+const MULTIPLICATIVE_DECREASE: f64 = 0.5;
+/// Minimum time between two decreases, so a burst of slow responses only counts once
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Builds the HTTP client used for all Hydra requests, together with its rate limiter
+pub fn hydra_client() -> Result<(ClientWithMiddleware, Arc<RateLimiter>)> {
+    let limiter = Arc::new(RateLimiter::from_env()?);
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(10);
+    let http_client =
+        ClientBuilder::new(reqwest::Client::builder().user_agent(USER_AGENT).build()?)
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            // Added after the retries so each retry is throttled as well
+            .with(RateLimitMiddleware(limiter.clone()))
+            .build();
+    Ok((http_client, limiter))
+}
+
+/// Adaptive token bucket shared by all requests of a client
+pub struct RateLimiter {
+    max_rate: f64,
+    latency_target: Duration,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Current number of requests per second
+    rate: f64,
+    /// Available tokens, at most one second worth of requests
+    tokens: f64,
+    last_refill: Instant,
+    /// Set when Hydra sent a `Retry-After` header
+    blocked_until: Option<Instant>,
+    last_decrease: Option<Instant>,
+    /// Number of requests that are currently waiting
+    waiting: usize,
+    /// Since when requests are waiting
+    waiting_since: Option<Instant>,
+    // Statistics
+    requests: u64,
+    /// Wall-clock time any request waited, so concurrent waits only count once
+    throttled: Duration,
+    backoff_responses: u64,
+    slow_responses: u64,
+    /// Requests that failed or got an error status, including retries
+    errors: u64,
+}
+
+impl RateLimiter {
+    /// Creates a limiter with the given maximum rate
+    pub fn new(max_rate: f64, latency_target: Duration) -> Self {
+        Self {
+            max_rate,
+            latency_target,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                rate: max_rate,
+                tokens: 1.0,
+                last_refill: Instant::now(),
+                blocked_until: None,
+                last_decrease: None,
+                waiting: 0,
+                waiting_since: None,
+                requests: 0,
+                throttled: Duration::ZERO,
+                backoff_responses: 0,
+                slow_responses: 0,
+                errors: 0,
+            }),
+        }
+    }
+
+    /// Creates a limiter configured by the environment
+    pub fn from_env() -> Result<Self> {
+        let max_rate = match std::env::var("ZHF_REQUESTS_PER_SECOND") {
+            Ok(rate) => rate.parse::<f64>()?.max(MIN_REQUESTS_PER_SECOND),
+            Err(_) => DEFAULT_REQUESTS_PER_SECOND,
+        };
+        let latency_target = match std::env::var("ZHF_LATENCY_TARGET_MS") {
+            Ok(ms) => Duration::from_millis(ms.parse::<u64>()?),
+            Err(_) => DEFAULT_LATENCY_TARGET,
+        };
+        Ok(Self::new(max_rate, latency_target))
+    }
+
+    /// Waits until a request may be sent
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * state.rate).min(state.rate.max(1.0));
+                state.last_refill = now;
+                match state.blocked_until {
+                    Some(until) if until > now => until - now,
+                    _ if state.tokens >= 1.0 => {
+                        state.tokens -= 1.0;
+                        state.requests += 1;
+                        return;
+                    }
+                    _ => Duration::from_secs_f64((1.0 - state.tokens) / state.rate),
+                }
+            };
+            let _waiting = Waiting::new(self);
+            sleep(wait).await;
+        }
+    }
+
+    /// Adjusts the rate based on a response
+    fn observe(&self, status: StatusCode, latency: Duration, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let backoff =
+            status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE;
+        if backoff {
+            state.backoff_responses += 1;
+            if let Some(retry_after) = retry_after {
+                log::warn!("Hydra asked us to retry after {}s", retry_after.as_secs());
+                let until = now + retry_after;
+                state.blocked_until = Some(state.blocked_until.map_or(until, |u| u.max(until)));
+            }
+        } else if latency > self.latency_target {
+            state.slow_responses += 1;
+        } else {
+            state.rate = (state.rate + ADDITIVE_INCREASE).min(self.max_rate);
+            return;
+        }
+        if state
+            .last_decrease
+            .is_none_or(|last| now.duration_since(last) >= DECREASE_COOLDOWN)
+        {
+            state.rate = (state.rate * MULTIPLICATIVE_DECREASE).max(MIN_REQUESTS_PER_SECOND);
+            state.last_decrease = Some(now);
+            log::debug!("Reduced request rate to {:.2}/s", state.rate);
+        }
+    }
+
+    /// Counts a request that failed or got an error status
+    fn count_error(&self) {
+        self.state.lock().unwrap().errors += 1;
+    }
+
+    /// Logs how much the limiter throttled the requests
+    pub fn log_stats(&self) {
+        let state = self.state.lock().unwrap();
+        log::info!(
+            "Sent {} requests, throttled for {:.1}s in total, {} backoff responses, {} slow responses, {} errors, final rate {:.2}/s",
+            state.requests,
+            state.throttled.as_secs_f64(),
+            state.backoff_responses,
+            state.slow_responses,
+            state.errors,
+            state.rate,
+        );
+    }
+
+    /// Writes the statistics of a crawl to `crawlstats/{crawler}`, replacing the ones of the
+    /// previous crawl. The duration is counted from the creation of the limiter.
+    pub fn write_stats(&self, data_dir: &Path, crawler: &str) -> Result<()> {
+        let stats = {
+            let state = self.state.lock().unwrap();
+            format!(
+                "finished_timestamp_seconds {}\nduration_seconds {:.3}\nrequests {}\nerrors {}\nbackoff_responses {}\nslow_responses {}\nthrottled_seconds {:.3}\n",
+                SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                self.started.elapsed().as_secs_f64(),
+                state.requests,
+                state.errors,
+                state.backoff_responses,
+                state.slow_responses,
+                state.throttled.as_secs_f64(),
+            )
+        };
+        let dir = data_dir.join("crawlstats");
+        create_dir_all(&dir)?;
+        std::fs::write(dir.join(crawler), stats)?;
+        Ok(())
+    }
+}
+
+/// A request waiting for the limiter, which counts as throttled time as long as it exists
+struct Waiting<'a>(&'a RateLimiter);
+
+impl<'a> Waiting<'a> {
+    fn new(limiter: &'a RateLimiter) -> Self {
+        let mut state = limiter.state.lock().unwrap();
+        if state.waiting == 0 {
+            state.waiting_since = Some(Instant::now());
+        }
+        state.waiting += 1;
+        Self(limiter)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.waiting -= 1;
+        if state.waiting == 0 {
+            if let Some(since) = state.waiting_since.take() {
+                state.throttled += since.elapsed();
+            }
+        }
+    }
+}
+
+/// Middleware that sends all requests through a [`RateLimiter`]
+struct RateLimitMiddleware(Arc<RateLimiter>);
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        self.0.acquire().await;
+        let start = Instant::now();
+        let res = next.run(req, extensions).await;
+        match &res {
+            Ok(res) if res.status().is_client_error() || res.status().is_server_error() => {
+                self.0.count_error();
+            }
+            Ok(_) => {}
+            Err(_) => self.0.count_error(),
+        }
+        if let Ok(res) = &res {
+            let retry_after = res
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            self.0.observe(res.status(), start.elapsed(), retry_after);
+        }
+        res
+    }
+}
+
+/// Parses a `Retry-After` header, which is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
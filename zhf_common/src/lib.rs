@@ -0,0 +1,5 @@
+//! Code shared between the zh.fail tools
+
+pub mod cache;
+pub mod http;
+pub mod store_path;
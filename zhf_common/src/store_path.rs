@@ -0,0 +1,136 @@
+//! Parsing of Nix store paths as shown by Hydra
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Name suffixes of fixed-output derivations that fetch sources
+const SOURCE_SUFFIXES: &[&str] = &[
+    "-source",
+    "-src",
+    "-vendor",
+    "-vendor.tar.gz",
+    "-go-modules",
+    "-deps",
+    ".tar.gz",
+    ".tgz",
+    ".tar.bz2",
+    ".tbz2",
+    ".tar.xz",
+    ".txz",
+    ".tar.zst",
+    ".tar.lz",
+    ".tar",
+    ".zip",
+    ".gem",
+    ".jar",
+    ".whl",
+    ".crate",
+    ".patch",
+    ".diff",
+    ".deb",
+    ".rpm",
+    ".dmg",
+    ".AppImage",
+];
+
+/// Length of the hash part of a store path
+const HASH_LEN: usize = 32;
+/// Characters of the base32 alphabet used by Nix
+const BASE32_CHARS: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+
+/// A store path like `/nix/store/<hash>-<name>`, in any store directory
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StorePath {
+    store_dir: String,
+    hash: String,
+    name: String,
+}
+
+impl StorePath {
+    /// Directory of the store, like `/nix/store`
+    pub fn store_dir(&self) -> &str {
+        &self.store_dir
+    }
+
+    /// The hash part of the base name
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// The name part of the base name, like `hello-2.12`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Parses the comma-separated store paths of all outputs of a build step
+    pub fn parse_outputs(paths: &str) -> Result<Vec<Self>> {
+        let outputs = paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Self>>>()?;
+        if outputs.is_empty() {
+            return Err(anyhow!("No store path found in {paths:?}"));
+        }
+        Ok(outputs)
+    }
+
+    /// Returns the name of the derivation that produced the outputs. This is the name of the
+    /// output all other output names start with (`out`), or the name of the first output.
+    pub fn derivation_name(outputs: &[Self]) -> Option<&str> {
+        let shortest = outputs
+            .iter()
+            .map(Self::name)
+            .min_by_key(|name| name.len())?;
+        if outputs.iter().all(|o| o.name.starts_with(shortest)) {
+            Some(shortest)
+        } else {
+            outputs.first().map(Self::name)
+        }
+    }
+}
+
+/// Whether a store path name looks like the output of a fixed-output derivation fetching sources
+pub fn looks_like_source(name: &str) -> bool {
+    name == "source" || SOURCE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+impl FromStr for StorePath {
+    type Err = anyhow::Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        let (store_dir, base_name) = path
+            .rsplit_once('/')
+            .ok_or_else(|| anyhow!("Store path {path:?} has no store directory"))?;
+        if !store_dir.starts_with('/') {
+            return Err(anyhow!("Store directory of {path:?} is not absolute"));
+        }
+        let (hash, name) = base_name
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Store path {path:?} has no name"))?;
+        if hash.len() != HASH_LEN || !hash.chars().all(|c| BASE32_CHARS.contains(c)) {
+            return Err(anyhow!("Store path {path:?} has an invalid hash part"));
+        }
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-._?=".contains(c))
+        {
+            return Err(anyhow!("Store path {path:?} has an invalid name"));
+        }
+        Ok(Self {
+            store_dir: store_dir.to_string(),
+            hash: hash.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for StorePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}-{}", self.store_dir, self.hash, self.name)
+    }
+}